@@ -5,6 +5,63 @@ struct Arg {
     cmd: Cmd,
 }
 
+/// Http settings for connecting to the log-collector.
+#[derive(Debug, clap::Args)]
+struct HttpArgs {
+    /// Timeout in seconds for connecting to the log-collector. Zero
+    /// disables the timeout.
+    #[arg(
+        long,
+        env = "LOG_SENDER_CONNECT_TIMEOUT_SECONDS",
+        default_value_t = 10
+    )]
+    connect_timeout_seconds: u64,
+
+    /// Timeout in seconds for each read from the log-collector
+    /// connection. Zero disables the timeout.
+    #[arg(long, env = "LOG_SENDER_READ_TIMEOUT_SECONDS", default_value_t = 60)]
+    read_timeout_seconds: u64,
+
+    /// Send all requests through this proxy,
+    /// e.g. `http://proxy.local:3128`.
+    #[arg(long, env = "LOG_SENDER_PROXY")]
+    proxy: Option<String>,
+
+    /// Specify one or more PEM files of additional root certificates to
+    /// trust. Separate paths with commas if using an environment
+    /// variable.
+    #[arg(long, env = "LOG_SENDER_CA_CERTS", value_delimiter = ',')]
+    ca_cert: Vec<std::path::PathBuf>,
+
+    /// PEM client certificate chain for mutual TLS.
+    #[arg(long, env = "LOG_SENDER_CLIENT_CERT", requires = "client_key")]
+    client_cert: Option<std::path::PathBuf>,
+
+    /// PEM PKCS#8 private key for the client certificate.
+    #[arg(long, env = "LOG_SENDER_CLIENT_KEY", requires = "client_cert")]
+    client_key: Option<std::path::PathBuf>,
+
+    /// Pin the log-collector certificate to one or more base64 SHA-256
+    /// hashes of its public key, e.g. `sha256//AAAA...=`. Separate pins
+    /// with commas if using an environment variable.
+    #[arg(long, env = "LOG_SENDER_PIN_SPKI_SHA256", value_delimiter = ',')]
+    pin_spki_sha256: Vec<String>,
+}
+
+impl From<HttpArgs> for log_sender::client::HttpConfig {
+    fn from(http: HttpArgs) -> Self {
+        Self {
+            connect_timeout_seconds: http.connect_timeout_seconds,
+            read_timeout_seconds: http.read_timeout_seconds,
+            proxy: http.proxy,
+            ca_cert_path_list: http.ca_cert,
+            client_cert_path: http.client_cert,
+            client_key_path: http.client_key,
+            pinned_spki_sha256_list: http.pin_spki_sha256,
+        }
+    }
+}
+
 #[derive(Debug, clap::Subcommand)]
 enum Cmd {
     /// Initialize a new config file. Note, this will generate a new
@@ -21,6 +78,33 @@ enum Cmd {
         #[arg(long, env = "LOG_SENDER_ENDPOINT")]
         endpoint: String,
 
+        /// Base64 public key of the collector at `--endpoint`. If set, every
+        /// batch it accepts must come with a receipt signed by this key.
+        /// Keys for extra endpoints are set per endpoint in the config file.
+        #[arg(long, env = "LOG_SENDER_COLLECTOR_PUB_KEY")]
+        collector_pub_key: Option<String>,
+
+        /// Specify one or more additional log-collector endpoints, e.g. a
+        /// regional fallback. The drone is registered with each of them.
+        /// Separate urls with commas if using an environment variable.
+        #[arg(long, env = "LOG_SENDER_EXTRA_ENDPOINTS", value_delimiter = ',')]
+        extra_endpoint: Vec<String>,
+
+        /// How proofs are sent when there are extra endpoints: `failover`
+        /// to the first healthy endpoint, or `fan-out` to all of them.
+        #[arg(
+            long,
+            env = "LOG_SENDER_ENDPOINT_MODE",
+            default_value = "failover"
+        )]
+        endpoint_mode: log_sender::collectors::EndpointMode,
+
+        /// In fan-out mode, whether `any` or `all` endpoints must accept a
+        /// batch before moving on. With `any`, an endpoint that fails misses
+        /// the batches the others accepted.
+        #[arg(long, env = "LOG_SENDER_ACK_POLICY", default_value = "all")]
+        ack_policy: log_sender::collectors::AckPolicy,
+
         /// Base64 Unyt Public Key for registration.
         #[arg(long, env = "LOG_SENDER_UNYT_PUB_KEY")]
         unyt_pub_key: String,
@@ -31,12 +115,26 @@ enum Cmd {
 
         /// Specify one or more paths to directories that will contain log files
         /// with entries to be published as log-collector metrics. The sender
-        /// will parse all files ending in a `.jsonl` extension. Specify
+        /// will parse all files ending in a `.jsonl` extension, as well as
+        /// gzip or zstd compressed `.jsonl.gz` and `.jsonl.zst` files. A path may
+        /// instead be a glob pattern, e.g.
+        /// `/var/log/holochain/**/hc-report*.jsonl*`. Specify
         /// this argument multiple times on the command line, or if using
         /// an environment variable, separate the paths with commas.
         #[arg(long, env = "LOG_SENDER_REPORT_PATHS", value_delimiter = ',')]
         report_path: Vec<std::path::PathBuf>,
 
+        /// Glob patterns of report files to skip, e.g. `**/*-debug.jsonl`.
+        /// Specify this argument multiple times on the command line, or if
+        /// using an environment variable, separate the patterns with commas.
+        #[arg(long, env = "LOG_SENDER_REPORT_EXCLUDE", value_delimiter = ',')]
+        report_exclude: Vec<String>,
+
+        /// Maximum directory depth searched below the literal prefix of a
+        /// report path glob pattern. Unlimited if not set.
+        #[arg(long, env = "LOG_SENDER_REPORT_MAX_DEPTH")]
+        report_max_depth: Option<usize>,
+
         /// Specify one or more conductor config paths. These will be used
         /// to report on database sizes on-disk at the reporting interval.
         /// Specify this argument multiple times on the command line, or if
@@ -47,10 +145,14 @@ enum Cmd {
             value_delimiter = ','
         )]
         conductor_config_path: Vec<std::path::PathBuf>,
+
+        #[command(flatten)]
+        http: HttpArgs,
     },
 
     /// Register DNA hashes with agreements and optional price sheets for a
-    /// drone.
+    /// drone. Every endpoint is called, even if one fails, and the outcome
+    /// for each is printed.
     RegisterDna {
         /// Specify a full path to a config file,
         /// e.g. `/var/run/log-sender-runtime.json`.
@@ -70,8 +172,185 @@ enum Cmd {
         price_sheet_hash: Option<String>,
 
         /// Optionally include additional json metadata.
-        #[arg(long, env = "LOG_SENDER_METADATA")]
-        metadata: Option<String>,
+        #[arg(long, env = "LOG_SENDER_METADATA", value_parser = parse_metadata)]
+        metadata: Option<serde_json::Value>,
+    },
+
+    /// Register every DNA listed in a manifest file with every endpoint.
+    /// Entries that are already registered with an endpoint are skipped, or
+    /// updated if their price sheet or metadata changed, so this is safe to
+    /// re-run, e.g. to catch up an endpoint that was down.
+    RegisterDnas {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// YAML or JSON list of entries with `dnaHash`, `agreementId`,
+        /// and optional `priceSheetHash` and `metadata`.
+        #[arg(long, env = "LOG_SENDER_DNA_MANIFEST")]
+        manifest: std::path::PathBuf,
+    },
+
+    /// Update the price sheet and metadata of a registered DNA.
+    UpdateDna {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// The registered dna hash.
+        #[arg(long, env = "LOG_SENDER_DNA_HASH")]
+        dna_hash: String,
+
+        /// The agreement id the dna is registered under.
+        #[arg(long, env = "LOG_SENDER_AGREEMENT_ID")]
+        agreement_id: String,
+
+        /// The new price-sheet hash.
+        #[arg(long, env = "LOG_SENDER_PRICE_SHEET_HASH")]
+        price_sheet_hash: Option<String>,
+
+        /// The new json metadata.
+        #[arg(long, env = "LOG_SENDER_METADATA", value_parser = parse_metadata)]
+        metadata: Option<serde_json::Value>,
+    },
+
+    /// Remove a DNA registration.
+    UnregisterDna {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// The registered dna hash.
+        #[arg(long, env = "LOG_SENDER_DNA_HASH")]
+        dna_hash: String,
+
+        /// The agreement id the dna is registered under.
+        #[arg(long, env = "LOG_SENDER_AGREEMENT_ID")]
+        agreement_id: String,
+    },
+
+    /// List the DNAs registered for a drone with each endpoint.
+    ListDnas {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// Print json instead of a table.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Register the DNAs hosted by the configured conductors that are not
+    /// yet registered with each endpoint, and warn about hosted DNAs with
+    /// no agreement.
+    SyncDnas {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// YAML or JSON file mapping dna hashes to an `agreementId`, and
+        /// optional `priceSheetHash` and `metadata`. The key `*` applies
+        /// to any dna not listed.
+        #[arg(long, env = "LOG_SENDER_AGREEMENTS_FILE")]
+        agreements_file: std::path::PathBuf,
+
+        /// Only report what would be registered.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print the state of a drone, including counts of malformed report
+    /// lines. This can be run while the service is running.
+    Status {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+    },
+
+    /// Retire a drone: flush any unsent proofs, deregister from the
+    /// log-collector, then delete the config file and its key material.
+    /// The service must be stopped first.
+    Decommission {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// Keep a copy of the config file, including the drone secret key,
+        /// at this path before deleting the original.
+        #[arg(long)]
+        archive: Option<std::path::PathBuf>,
+    },
+
+    /// For hosts without network access: sign any new db sizes and report
+    /// proofs and append them to a bundle file instead of sending them.
+    /// Progress is recorded once the bundle is written, so each export
+    /// only contains new proofs. The service must be stopped first.
+    /// Requests are signed at export time and the collector may reject
+    /// stale signatures, so upload the bundle promptly, `upload` warns about
+    /// requests signed more than a day ago.
+    Export {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// The bundle file to append to, e.g. `bundle.jsonl`.
+        #[arg(long)]
+        out: std::path::PathBuf,
+    },
+
+    /// Submit a bundle written by `export` to the log-collector, e.g. from
+    /// a connected machine. Does not need the drone config. Safe to re-run
+    /// if interrupted.
+    Upload {
+        /// The bundle file to submit.
+        bundle: std::path::PathBuf,
+
+        /// Specify the endpoint url of the log-collector endpoint,
+        /// e.g. `https://log-collector.my.url`.
+        #[arg(long, env = "LOG_SENDER_ENDPOINT")]
+        endpoint: String,
+
+        /// Base64 public key of the collector. If set, every accepted
+        /// request must come with a receipt signed by this key, and the
+        /// receipts are stored next to the bundle with a `.receipts` suffix.
+        #[arg(long, env = "LOG_SENDER_COLLECTOR_PUB_KEY")]
+        collector_pub_key: Option<String>,
+
+        #[command(flatten)]
+        http: HttpArgs,
+    },
+
+    /// Re-send the report proofs in a time range, e.g. after collector data
+    /// loss. The range is limited to what has already been sent, and the
+    /// regular checkpoint is not changed. Re-run with the same arguments
+    /// to resume an interrupted replay. The service must be stopped first.
+    Replay {
+        /// Specify a full path to a config file,
+        /// e.g. `/var/run/log-sender-runtime.json`.
+        #[arg(long, env = "LOG_SENDER_CONFIG_FILE")]
+        config_file: std::path::PathBuf,
+
+        /// First report timestamp to send, inclusive, in microseconds as
+        /// written in the report files.
+        #[arg(long)]
+        from: u64,
+
+        /// Last report timestamp to send, inclusive.
+        #[arg(long)]
+        to: u64,
+
+        /// Report kinds to send. Specify multiple times for more than one.
+        /// Defaults to `fetchedOps`.
+        #[arg(long)]
+        kind: Vec<String>,
     },
 
     /// Run the service, polling a log-file directory for metrics to
@@ -109,17 +388,40 @@ async fn main() {
         Cmd::Init {
             config_file,
             endpoint,
+            collector_pub_key,
+            extra_endpoint,
+            endpoint_mode,
+            ack_policy,
             unyt_pub_key,
             report_interval_seconds,
             report_path,
+            report_exclude,
+            report_max_depth,
             conductor_config_path,
+            http,
         } => log_sender::initialize(
             config_file,
             endpoint,
+            collector_pub_key,
             unyt_pub_key,
             report_interval_seconds,
             report_path,
+            report_exclude,
+            report_max_depth,
             conductor_config_path,
+            http.into(),
+            log_sender::collectors::EndpointConfig {
+                extra_endpoints: extra_endpoint
+                    .into_iter()
+                    .map(|endpoint| log_sender::collectors::ExtraEndpoint {
+                        endpoint,
+                        drone_id: 0,
+                        collector_pub_key: None,
+                    })
+                    .collect(),
+                endpoint_mode,
+                ack_policy,
+            },
         )
         .await
         .unwrap(),
@@ -130,15 +432,139 @@ async fn main() {
             price_sheet_hash,
             metadata,
         } => {
-            let out = log_sender::register_dna(
-                config_file,
-                dna_hash,
-                agreement_id,
-                price_sheet_hash,
-                metadata.map(|s| serde_json::from_str(&s).unwrap()),
-            )
-            .await
-            .unwrap();
+            let out = or_exit(
+                log_sender::register_dna(
+                    config_file,
+                    dna_hash,
+                    agreement_id,
+                    price_sheet_hash,
+                    metadata,
+                )
+                .await,
+            );
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            exit_on_failure(&out);
+        }
+        Cmd::RegisterDnas {
+            config_file,
+            manifest,
+        } => {
+            let out =
+                or_exit(log_sender::register_dnas(config_file, manifest).await);
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            if out.iter().any(|r| {
+                r.status == log_sender::dna_sync::ManifestStatus::Failed
+            }) {
+                std::process::exit(1);
+            }
+        }
+        Cmd::UpdateDna {
+            config_file,
+            dna_hash,
+            agreement_id,
+            price_sheet_hash,
+            metadata,
+        } => {
+            let out = or_exit(
+                log_sender::update_dna(
+                    config_file,
+                    dna_hash,
+                    agreement_id,
+                    price_sheet_hash,
+                    metadata,
+                )
+                .await,
+            );
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            exit_on_failure(&out);
+        }
+        Cmd::UnregisterDna {
+            config_file,
+            dna_hash,
+            agreement_id,
+        } => {
+            let out = or_exit(
+                log_sender::unregister_dna(config_file, dna_hash, agreement_id)
+                    .await,
+            );
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            exit_on_failure(&out);
+        }
+        Cmd::ListDnas { config_file, json } => {
+            let out = or_exit(log_sender::list_dnas(config_file).await);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            } else {
+                for (idx, res) in out.iter().enumerate() {
+                    if out.len() > 1 {
+                        if idx > 0 {
+                            println!();
+                        }
+                        println!("{}:", res.endpoint);
+                    }
+                    match (&res.response, &res.error) {
+                        (Some(dnas), _) => print_dna_table(dnas),
+                        (_, Some(err)) => eprintln!("Error: {err}"),
+                        _ => (),
+                    }
+                }
+            }
+            exit_on_failure(&out);
+        }
+        Cmd::SyncDnas {
+            config_file,
+            agreements_file,
+            dry_run,
+        } => {
+            let out = or_exit(
+                log_sender::sync_dnas(config_file, agreements_file, dry_run)
+                    .await,
+            );
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            if out.iter().any(|r| r.error.is_some()) {
+                std::process::exit(1);
+            }
+        }
+        Cmd::Status { config_file } => {
+            let out = or_exit(log_sender::status(config_file).await);
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+        }
+        Cmd::Decommission {
+            config_file,
+            archive,
+        } => or_exit(log_sender::decommission(config_file, archive).await),
+        Cmd::Export { config_file, out } => {
+            let out = or_exit(log_sender::export(config_file, out).await);
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+        }
+        Cmd::Upload {
+            bundle,
+            endpoint,
+            collector_pub_key,
+            http,
+        } => {
+            let out = or_exit(
+                log_sender::upload(
+                    endpoint,
+                    bundle,
+                    http.into(),
+                    collector_pub_key,
+                )
+                .await,
+            );
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+            if out.error.is_some() {
+                std::process::exit(1);
+            }
+        }
+        Cmd::Replay {
+            config_file,
+            from,
+            to,
+            kind,
+        } => {
+            let out =
+                or_exit(log_sender::replay(config_file, from, to, kind).await);
             println!("{}", serde_json::to_string_pretty(&out).unwrap());
         }
         Cmd::Service { config_file } => {
@@ -146,3 +572,65 @@ async fn main() {
         }
     }
 }
+
+/// Parse `--metadata`, reporting where the json is malformed.
+fn parse_metadata(s: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(s).map_err(|err| format!("invalid json: {err}"))
+}
+
+/// Unwrap a result, or print the error and exit, for errors caused by bad
+/// user input rather than bugs.
+fn or_exit<T>(res: std::io::Result<T>) -> T {
+    res.unwrap_or_else(|err| {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    })
+}
+
+/// Exit with an error status if the call failed at any endpoint, after the
+/// outcome of each has been printed.
+fn exit_on_failure<T>(results: &[log_sender::collectors::EndpointResult<T>]) {
+    if results.iter().any(|r| !r.is_ok()) {
+        std::process::exit(1);
+    }
+}
+
+fn print_dna_table(dnas: &[log_sender::client::DnaRegistration]) {
+    let header = ["DNA HASH", "AGREEMENT ID", "PRICE SHEET", "METADATA"];
+    let rows: Vec<[String; 4]> = dnas
+        .iter()
+        .map(|d| {
+            [
+                d.dna_hash.clone(),
+                d.agreement_id.clone(),
+                d.price_sheet_hash.clone().unwrap_or_else(|| "-".into()),
+                d.metadata
+                    .as_ref()
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| "-".into()),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|h| h.len());
+    for row in rows.iter() {
+        for (w, cell) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(cell.len());
+        }
+    }
+
+    let print_row = |row: [&str; 4]| {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, w)| format!("{cell:w$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(header);
+    for row in rows.iter() {
+        print_row(row.each_ref().map(|c| c.as_str()));
+    }
+}
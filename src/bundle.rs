@@ -0,0 +1,108 @@
+//! Bundles of signed metrics requests, for hosts that cannot reach the
+//! collector directly. Each line of a bundle is a complete "/metrics"
+//! request body, as signed by the drone.
+
+use crate::*;
+
+/// Upload warns about bundle requests signed longer ago than this. The
+/// collector may reject requests whose signature timestamp is too old.
+pub const STALE_BUNDLE_SECONDS: u64 = 24 * 60 * 60;
+
+/// Appends signed metrics requests to a bundle file.
+pub struct BundleWriter {
+    file: tokio::sync::Mutex<tokio::fs::File>,
+}
+
+impl BundleWriter {
+    /// Open `path` for appending, creating it if needed.
+    pub async fn open(path: &std::path::Path) -> Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|err| {
+                std::io::Error::new(
+                    err.kind(),
+                    format!("{}: {err}", path.display()),
+                )
+            })?;
+
+        Ok(Self {
+            file: tokio::sync::Mutex::new(file),
+        })
+    }
+
+    /// Append a signed request.
+    pub async fn write(&self, signed: &SignedMetrics) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut line = signed.body.clone();
+        line.push('\n');
+        self.file.lock().await.write_all(line.as_bytes()).await
+    }
+
+    /// Flush everything written so far to disk.
+    pub async fn sync(&self) -> Result<()> {
+        self.file.lock().await.sync_data().await
+    }
+}
+
+/// Path of the file receipts for a bundle's requests are stored in, the
+/// bundle path with a `.receipts` suffix.
+pub fn bundle_receipt_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(".receipts");
+    path.into()
+}
+
+/// Read the signed requests in a bundle file.
+pub async fn read_bundle(path: &std::path::Path) -> Result<Vec<SignedMetrics>> {
+    let data = tokio::fs::read_to_string(path).await.map_err(|err| {
+        std::io::Error::new(err.kind(), format!("{}: {err}", path.display()))
+    })?;
+
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            SignedMetrics::from_body(line.to_string()).map_err(|err| {
+                std::io::Error::other(format!(
+                    "{}:{}: {err}",
+                    path.display(),
+                    idx + 1,
+                ))
+            })
+        })
+        .collect()
+}
+
+/// The outcome of an export.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReport {
+    /// Number of signed requests written.
+    pub requests: usize,
+
+    /// Number of proofs across those requests.
+    pub proofs: usize,
+}
+
+/// The outcome of an upload.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadReport {
+    /// Number of requests in the bundle.
+    pub requests: usize,
+
+    /// Number of requests the collector accepted, including ones it had
+    /// already received.
+    pub uploaded: usize,
+
+    /// Number of verified receipts stored next to the bundle.
+    pub receipts: usize,
+
+    /// The error that stopped the upload, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
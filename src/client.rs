@@ -4,38 +4,444 @@ use super::*;
 
 pub use reqwest;
 
+/// Content encoding of "/metrics" request bodies.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum RequestCompression {
+    /// Send bodies uncompressed.
+    #[default]
+    Identity,
+
+    /// `Content-Encoding: gzip`.
+    Gzip,
+
+    /// `Content-Encoding: zstd`.
+    Zstd,
+}
+
+impl RequestCompression {
+    /// The `Content-Encoding` header value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    async fn encode(&self, body: &[u8]) -> Result<Vec<u8>> {
+        use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
+        use tokio::io::AsyncWriteExt;
+
+        Ok(match self {
+            Self::Identity => body.to_vec(),
+            Self::Gzip => {
+                let mut enc = GzipEncoder::new(Vec::new());
+                enc.write_all(body).await?;
+                enc.shutdown().await?;
+                enc.into_inner()
+            }
+            Self::Zstd => {
+                let mut enc = ZstdEncoder::new(Vec::new());
+                enc.write_all(body).await?;
+                enc.shutdown().await?;
+                enc.into_inner()
+            }
+        })
+    }
+}
+
+/// Http settings for connecting to the collector.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpConfig {
+    /// Timeout for establishing a connection. Zero disables it.
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
+
+    /// Timeout for each read from the connection. Zero disables it.
+    #[serde(default = "default_read_timeout_seconds")]
+    pub read_timeout_seconds: u64,
+
+    /// Explicit proxy url for all requests, e.g. `http://proxy:3128`.
+    /// Without it, the standard proxy environment variables are used.
+    #[serde(default)]
+    pub proxy: Option<String>,
+
+    /// PEM files of additional root certificates to trust.
+    #[serde(default)]
+    pub ca_cert_path_list: Vec<std::path::PathBuf>,
+
+    /// PEM client certificate chain for mutual TLS.
+    #[serde(default)]
+    pub client_cert_path: Option<std::path::PathBuf>,
+
+    /// PEM PKCS#8 private key for the client certificate.
+    #[serde(default)]
+    pub client_key_path: Option<std::path::PathBuf>,
+
+    /// If not empty, the collector's certificate public key must match one
+    /// of these base64 SHA-256 SPKI hashes. An optional `sha256//` prefix
+    /// is accepted. The pins are checked during the TLS handshake, so
+    /// nothing is sent to a collector that doesn't match.
+    #[serde(default)]
+    pub pinned_spki_sha256_list: Vec<String>,
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_read_timeout_seconds() -> u64 {
+    60
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+            read_timeout_seconds: default_read_timeout_seconds(),
+            proxy: None,
+            ca_cert_path_list: Vec::new(),
+            client_cert_path: None,
+            client_key_path: None,
+            pinned_spki_sha256_list: Vec::new(),
+        }
+    }
+}
+
 /// Log-collector low-level http client.
 pub struct Client {
     client: reqwest::Client,
     url: reqwest::Url,
+    compression: RequestCompression,
+    // content encodings the collector advertised in its health response,
+    // `None` until a health call has been made
+    accept_encodings: std::sync::Mutex<Option<Vec<String>>>,
+    limiter: RateLimiter,
+    skew: ClockSkew,
+    collector_pub_key: Option<PubKey>,
 }
 
+/// Offset of the collector's clock from the local clock.
+#[derive(Default)]
+struct ClockSkew {
+    offset_ms: std::sync::atomic::AtomicI64,
+    measured: std::sync::atomic::AtomicBool,
+    warn_ms: std::sync::atomic::AtomicU64,
+    correct: std::sync::atomic::AtomicBool,
+}
+
+/// Default clock skew, in seconds, above which a warning is logged.
+pub const DEFAULT_CLOCK_SKEW_WARN_SECONDS: u64 = 30;
+
+/// How many times a request is retried after a 429 response.
+const MAX_RATE_LIMITED_RETRIES: u32 = 3;
+
 impl Client {
-    /// Construct a new [Client] instance.
+    /// Construct a new [Client] instance with default http settings.
     pub async fn new(url: reqwest::Url) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .build()
-            .map_err(std::io::Error::other)?;
+        Self::with_config(url, &HttpConfig::default()).await
+    }
+
+    /// Construct a new [Client] instance.
+    pub async fn with_config(
+        url: reqwest::Url,
+        http: &HttpConfig,
+    ) -> Result<Self> {
+        use std::time::Duration;
+
+        let mut builder = reqwest::Client::builder();
+
+        if http.connect_timeout_seconds > 0 {
+            builder = builder.connect_timeout(Duration::from_secs(
+                http.connect_timeout_seconds,
+            ));
+        }
+
+        if http.read_timeout_seconds > 0 {
+            builder = builder
+                .read_timeout(Duration::from_secs(http.read_timeout_seconds));
+        }
+
+        if let Some(proxy) = &http.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).map_err(std::io::Error::other)?,
+            );
+        }
+
+        let mut ca_certs = Vec::new();
+        for path in http.ca_cert_path_list.iter() {
+            ca_certs.push(tokio::fs::read(path).await?);
+        }
+
+        let identity = match (&http.client_cert_path, &http.client_key_path) {
+            (Some(cert), Some(key)) => Some((
+                tokio::fs::read(cert).await?,
+                tokio::fs::read(key).await?,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(std::io::Error::other(
+                    "client cert and client key must be set together",
+                ));
+            }
+        };
+
+        let pins: Vec<String> = http
+            .pinned_spki_sha256_list
+            .iter()
+            .map(|p| p.trim_start_matches("sha256//").to_string())
+            .collect();
+
+        if pins.is_empty() {
+            for pem in ca_certs.iter() {
+                for cert in reqwest::Certificate::from_pem_bundle(pem)
+                    .map_err(std::io::Error::other)?
+                {
+                    builder = builder.add_root_certificate(cert);
+                }
+            }
+
+            if let Some((cert, key)) = &identity {
+                builder = builder.identity(
+                    reqwest::Identity::from_pkcs8_pem(cert, key)
+                        .map_err(std::io::Error::other)?,
+                );
+            }
+        } else {
+            if url.scheme() != "https" {
+                return Err(std::io::Error::other(
+                    "certificate pinning requires https",
+                ));
+            }
+
+            // native-tls can't check the pins until after a request has
+            // been sent, so pinned clients verify with rustls instead
+            builder = builder.use_preconfigured_tls(pinned_tls_config(
+                &ca_certs,
+                identity.as_ref(),
+                pins,
+            )?);
+        }
+
+        let client = builder.build().map_err(std::io::Error::other)?;
+
+        Ok(Self {
+            client,
+            url,
+            compression: RequestCompression::Identity,
+            accept_encodings: std::sync::Mutex::new(None),
+            limiter: RateLimiter::default(),
+            skew: ClockSkew {
+                warn_ms: DEFAULT_CLOCK_SKEW_WARN_SECONDS
+                    .saturating_mul(1000)
+                    .into(),
+                ..Default::default()
+            },
+            collector_pub_key: None,
+        })
+    }
+
+    /// Limit the requests and bytes per second sent by this client, across
+    /// all of its calls. Zero disables the respective limit. This can be
+    /// changed at any time.
+    pub fn set_rate_limit(
+        &self,
+        requests_per_second: f64,
+        bytes_per_second: u64,
+    ) {
+        self.limiter
+            .set_limits(requests_per_second, bytes_per_second);
+    }
+
+    /// Log a warning when the collector's clock differs from ours by more
+    /// than `warn_seconds`. If `correct` is set, signature timestamps are
+    /// shifted by the measured offset.
+    pub fn set_clock_skew(&self, warn_seconds: u64, correct: bool) {
+        use std::sync::atomic::Ordering::Relaxed;
+        self.skew
+            .warn_ms
+            .store(warn_seconds.saturating_mul(1000), Relaxed);
+        self.skew.correct.store(correct, Relaxed);
+    }
+
+    /// The measured offset of the collector's clock from ours, in
+    /// milliseconds, if any response has been received yet.
+    pub fn clock_skew_ms(&self) -> Option<i64> {
+        use std::sync::atomic::Ordering::Relaxed;
+        self.skew
+            .measured
+            .load(Relaxed)
+            .then(|| self.skew.offset_ms.load(Relaxed))
+    }
+
+    /// Millisecond timestamp for signatures, corrected by the measured
+    /// clock skew if so configured.
+    pub fn signing_timestamp(&self) -> Result<u64> {
+        use std::sync::atomic::Ordering::Relaxed;
+        let now = unix_time()?.as_millis() as i64;
+        let offset = match self.skew.correct.load(Relaxed) {
+            true => self.clock_skew_ms().unwrap_or(0),
+            false => 0,
+        };
+        Ok(now.saturating_add(offset).max(0) as u64)
+    }
+
+    /// Record a collector timestamp. Measurements within `precision_ms` of
+    /// the current offset are ignored, so coarse `Date` headers don't
+    /// override a precise measurement.
+    fn observe_server_time(
+        &self,
+        server_ms: u64,
+        precision_ms: i64,
+    ) -> Result<()> {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let now = unix_time()?.as_millis() as i64;
+        let offset = server_ms as i64 - now;
 
-        Ok(Self { client, url })
+        let prev = self.clock_skew_ms();
+        if let Some(prev) = prev
+            && (offset - prev).abs() <= precision_ms
+        {
+            return Ok(());
+        }
+
+        self.skew.offset_ms.store(offset, Relaxed);
+        self.skew.measured.store(true, Relaxed);
+
+        // only warn when first crossing the threshold
+        let warn_ms = self.skew.warn_ms.load(Relaxed) as i64;
+        if offset.abs() > warn_ms
+            && prev.is_none_or(|prev| prev.abs() <= warn_ms)
+        {
+            tracing::warn!(
+                "local clock differs from the collector by {:.1}s{}",
+                offset as f64 / 1000.0,
+                match self.skew.correct.load(Relaxed) {
+                    true => ", correcting signature timestamps",
+                    false => "",
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The rate limiter shared by all calls of this client.
+    pub fn rate_limiter(&self) -> &RateLimiter {
+        &self.limiter
     }
 
-    /// Make a "health" call.
+    /// Send a request through the rate limiter. If the collector responds
+    /// with 429, all calls are paused for its `Retry-After` and the request
+    /// is retried, up to [MAX_RATE_LIMITED_RETRIES] times.
+    async fn send(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let req = req.build().map_err(std::io::Error::other)?;
+        let bytes = req
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| b.len())
+            .unwrap_or(0);
+
+        let mut attempt = 0;
+        loop {
+            // all our bodies are buffered, so this never fails
+            let this = req.try_clone().ok_or_else(|| {
+                std::io::Error::other("cannot retry a streaming request")
+            })?;
+
+            self.limiter.acquire(bytes).await;
+            let res = self
+                .client
+                .execute(this)
+                .await
+                .map_err(std::io::Error::other)?;
+
+            if let Some(date) = res.headers().get(reqwest::header::DATE)
+                && let Ok(date) = date.to_str()
+                && let Ok(date) = httpdate::parse_http_date(date)
+                && let Ok(date) = date.duration_since(std::time::UNIX_EPOCH)
+            {
+                // the date header is truncated to the second
+                self.observe_server_time(date.as_millis() as u64 + 500, 1000)?;
+            }
+
+            if res.status() != reqwest::StatusCode::TOO_MANY_REQUESTS
+                || attempt >= MAX_RATE_LIMITED_RETRIES
+            {
+                return Ok(res);
+            }
+
+            attempt += 1;
+            let wait = retry_after(&res)
+                .unwrap_or(std::time::Duration::from_secs(1 << attempt));
+            tracing::warn!("collector is rate limiting, pausing for {wait:?}");
+            self.limiter.pause(wait);
+        }
+    }
+
+    /// Require "/metrics" acknowledgements to carry a receipt signed by
+    /// this collector key.
+    pub fn set_collector_pub_key(&mut self, collector_pub_key: Option<PubKey>) {
+        self.collector_pub_key = collector_pub_key;
+    }
+
+    /// Compress "/metrics" request bodies, if the collector supports it.
+    pub fn set_request_compression(&mut self, compression: RequestCompression) {
+        self.compression = compression;
+    }
+
+    /// The encoding that will be used for the next "/metrics" request.
+    /// Falls back to identity unless the collector has advertised support
+    /// for the configured encoding.
+    pub fn request_compression(&self) -> RequestCompression {
+        if self.compression == RequestCompression::Identity {
+            return RequestCompression::Identity;
+        }
+
+        let accept = self.accept_encodings.lock().unwrap();
+        match &*accept {
+            Some(accept)
+                if accept.iter().any(|e| e == self.compression.as_str()) =>
+            {
+                self.compression
+            }
+            _ => RequestCompression::Identity,
+        }
+    }
+
+    /// Make a "health" call. A `time` field in the response, in
+    /// milliseconds since the epoch, is used to measure clock skew more
+    /// precisely than the `Date` header allows.
     pub async fn health(&self) -> Result<()> {
         let mut url = self.url.clone();
         url.set_path("/");
 
         #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
         struct R {
             status: String,
+            #[serde(default)]
+            content_encodings: Vec<String>,
+            #[serde(default)]
+            time: Option<u64>,
         }
 
-        let res = self
-            .client
-            .get(url)
-            .send()
-            .await
-            .map_err(std::io::Error::other)?;
+        let res = self.send(self.client.get(url)).await?;
 
         if res.error_for_status_ref().is_err() {
             return Err(std::io::Error::other(
@@ -52,6 +458,12 @@ impl Client {
             )));
         }
 
+        *self.accept_encodings.lock().unwrap() = Some(res.content_encodings);
+
+        if let Some(time) = res.time {
+            self.observe_server_time(time, 0)?;
+        }
+
         Ok(())
     }
 
@@ -74,10 +486,7 @@ impl Client {
 
         let drone_pub_key = config.drone_pub_key.clone();
         let unyt_pub_key = config.unyt_pub_key.clone();
-        let signature_timestamp = std::time::SystemTime::UNIX_EPOCH
-            .elapsed()
-            .expect("can get time")
-            .as_millis() as u64;
+        let signature_timestamp = self.signing_timestamp()?;
 
         #[derive(serde::Serialize)]
         #[serde(rename_all = "camelCase")]
@@ -107,17 +516,13 @@ impl Client {
         }
 
         let res = self
-            .client
-            .post(url)
-            .json(&Req {
+            .send(self.client.post(url).json(&Req {
                 drone_pub_key,
                 unyt_pub_key,
                 drone_signature,
                 signature_timestamp,
-            })
-            .send()
-            .await
-            .map_err(std::io::Error::other)?;
+            }))
+            .await?;
 
         if res.error_for_status_ref().is_err() {
             return Err(std::io::Error::other(
@@ -134,6 +539,95 @@ impl Client {
         Err(std::io::Error::other(format!("invalid response: {res:?}")))
     }
 
+    /// Make a "drone-deregistration" call, so the collector no longer
+    /// accepts metrics signed by this drone. A 404 with the
+    /// [UNKNOWN_DRONE_CODE] error code, meaning the collector doesn't know
+    /// the drone, is treated as already deregistered.
+    pub async fn deregister(
+        &self,
+        config: &RuntimeConfigFile,
+        drone_id: u64,
+    ) -> Result<()> {
+        let mut url = self.url.clone();
+        url.set_path("/drone-deregistration");
+
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Req {
+            drone_pub_key: String,
+            drone_id: u64,
+            drone_signature: String,
+            signature_timestamp: u64,
+        }
+
+        let drone_pub_key = config.drone_pub_key.clone();
+        let signature_timestamp = self.signing_timestamp()?;
+
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Sig {
+            drone_id: u64,
+            drone_pub_key: String,
+            timestamp: u64,
+        }
+
+        let sig = serde_json::to_string(&Sig {
+            drone_id,
+            drone_pub_key: drone_pub_key.clone(),
+            timestamp: signature_timestamp,
+        })?;
+
+        let drone_signature = config.rt_drone_sec_key.sign(sig.as_bytes())?;
+
+        let res = self
+            .send(self.client.post(url).json(&Req {
+                drone_pub_key,
+                drone_id,
+                drone_signature,
+                signature_timestamp,
+            }))
+            .await?;
+
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            #[derive(serde::Deserialize)]
+            struct NotFound {
+                code: String,
+            }
+
+            // a 404 from a proxy or a wrong path must not be mistaken for
+            // a successful deregistration
+            let body = res.text().await.map_err(std::io::Error::other)?;
+            return match serde_json::from_str::<NotFound>(&body) {
+                Ok(res) if res.code == UNKNOWN_DRONE_CODE => {
+                    tracing::debug!(drone_id, "drone already deregistered");
+                    Ok(())
+                }
+                _ => Err(std::io::Error::other(format!(
+                    "deregistration not found: {body}"
+                ))),
+            };
+        }
+
+        if res.error_for_status_ref().is_err() {
+            return Err(std::io::Error::other(
+                res.text().await.map_err(std::io::Error::other)?,
+            ));
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Res {
+            success: bool,
+        }
+
+        let res: Res = res.json().await.map_err(std::io::Error::other)?;
+
+        if res.success {
+            return Ok(());
+        }
+
+        Err(std::io::Error::other(format!("invalid response: {res:?}")))
+    }
+
     /// Make a "register-dna" call.
     pub async fn register_dna(
         &self,
@@ -143,8 +637,61 @@ impl Client {
         price_sheet_hash: Option<String>,
         metadata: Option<serde_json::Value>,
     ) -> Result<serde_json::Value> {
+        self.dna_call(
+            "register-dna",
+            config,
+            dna_hash,
+            agreement_id,
+            price_sheet_hash,
+            metadata,
+        )
+        .await
+    }
+
+    /// Make an "update-dna" call, replacing the price sheet and metadata of
+    /// an existing registration.
+    pub async fn update_dna(
+        &self,
+        config: &RuntimeConfigFile,
+        dna_hash: String,
+        agreement_id: String,
+        price_sheet_hash: Option<String>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value> {
+        self.dna_call(
+            "update-dna",
+            config,
+            dna_hash,
+            agreement_id,
+            price_sheet_hash,
+            metadata,
+        )
+        .await
+    }
+
+    /// Make a "register-dna" or "update-dna" call. The "update-dna" action
+    /// is signed, so its signature can't be replayed as a registration.
+    /// Registrations keep the original signed content.
+    async fn dna_call(
+        &self,
+        action: &'static str,
+        config: &RuntimeConfigFile,
+        dna_hash: String,
+        agreement_id: String,
+        price_sheet_hash: Option<String>,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<serde_json::Value> {
+        validate_dna_registration(
+            config,
+            &dna_hash,
+            &agreement_id,
+            price_sheet_hash.as_deref(),
+            metadata.as_ref(),
+        )
+        .await?;
+
         let mut url = self.url.clone();
-        url.set_path("/register-dna");
+        url.set_path(&format!("/{action}"));
 
         #[derive(serde::Serialize)]
         #[serde(rename_all = "camelCase")]
@@ -159,14 +706,13 @@ impl Client {
         }
 
         let drone_pub_key = config.drone_pub_key.clone();
-        let signature_timestamp = std::time::SystemTime::UNIX_EPOCH
-            .elapsed()
-            .expect("can get time")
-            .as_millis() as u64;
+        let signature_timestamp = self.signing_timestamp()?;
 
         #[derive(serde::Serialize)]
         #[serde(rename_all = "camelCase")]
         struct Sig {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            action: Option<&'static str>,
             drone_pub_key: String,
             dna_hash: String,
             agreement_id: String,
@@ -178,6 +724,7 @@ impl Client {
         }
 
         let sig = serde_json::to_string(&Sig {
+            action: (action != "register-dna").then_some(action),
             drone_pub_key: drone_pub_key.clone(),
             dna_hash: dna_hash.clone(),
             agreement_id: agreement_id.clone(),
@@ -189,9 +736,7 @@ impl Client {
         let drone_signature = config.rt_drone_sec_key.sign(sig.as_bytes())?;
 
         let res = self
-            .client
-            .post(url)
-            .json(&Req {
+            .send(self.client.post(url).json(&Req {
                 drone_pub_key,
                 dna_hash,
                 agreement_id,
@@ -199,75 +744,538 @@ impl Client {
                 drone_signature,
                 signature_timestamp,
                 metadata,
-            })
-            .send()
-            .await
-            .map_err(std::io::Error::other)?;
+            }))
+            .await?;
 
-        if res.error_for_status_ref().is_err() {
-            return Err(std::io::Error::other(
-                res.text().await.map_err(std::io::Error::other)?,
-            ));
+        success_json(res).await
+    }
+
+    /// Make an "unregister-dna" call, removing a registration.
+    pub async fn unregister_dna(
+        &self,
+        config: &RuntimeConfigFile,
+        dna_hash: String,
+        agreement_id: String,
+    ) -> Result<serde_json::Value> {
+        validate_dna_hash(&dna_hash)?;
+        validate_agreement_id(&agreement_id)?;
+
+        let mut url = self.url.clone();
+        url.set_path("/unregister-dna");
+
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Req {
+            drone_pub_key: String,
+            dna_hash: String,
+            agreement_id: String,
+            drone_signature: String,
+            signature_timestamp: u64,
         }
 
-        let res: serde_json::Value =
-            res.json().await.map_err(std::io::Error::other)?;
+        let drone_pub_key = config.drone_pub_key.clone();
+        let signature_timestamp = self.signing_timestamp()?;
 
-        if let Some(obj) = res.as_object()
-            && let Some(p) = obj.get("success")
-            && let Some(b) = p.as_bool()
-            && b
-        {
-            return Ok(res);
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Sig {
+            action: &'static str,
+            drone_pub_key: String,
+            dna_hash: String,
+            agreement_id: String,
+            timestamp: u64,
         }
 
-        Err(std::io::Error::other(format!("invalid response: {res:?}")))
+        let sig = serde_json::to_string(&Sig {
+            action: "unregister-dna",
+            drone_pub_key: drone_pub_key.clone(),
+            dna_hash: dna_hash.clone(),
+            agreement_id: agreement_id.clone(),
+            timestamp: signature_timestamp,
+        })?;
+
+        let drone_signature = config.rt_drone_sec_key.sign(sig.as_bytes())?;
+
+        let res = self
+            .send(self.client.post(url).json(&Req {
+                drone_pub_key,
+                dna_hash,
+                agreement_id,
+                drone_signature,
+                signature_timestamp,
+            }))
+            .await?;
+
+        success_json(res).await
     }
 
-    /// Submit metrics to the endpoint.
-    pub async fn metrics(
+    /// Make a "list-dnas" call, returning the DNAs registered for this
+    /// drone.
+    pub async fn list_dnas(
         &self,
         config: &RuntimeConfigFile,
-        proofs: Vec<String>,
-    ) -> Result<()> {
+    ) -> Result<Vec<DnaRegistration>> {
         let mut url = self.url.clone();
-        url.set_path("/metrics");
+        url.set_path("/list-dnas");
 
-        #[derive(Clone, serde::Serialize)]
+        #[derive(serde::Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct ReqMetric {
-            value: u64,
-            timestamp: u64,
-            #[serde(rename = "registered_unit_index")]
-            registered_unit_index: u64,
-            proof: String,
+        struct Req {
+            drone_pub_key: String,
+            drone_signature: String,
+            signature_timestamp: u64,
         }
 
+        let drone_pub_key = config.drone_pub_key.clone();
+        let signature_timestamp = self.signing_timestamp()?;
+
         #[derive(serde::Serialize)]
         #[serde(rename_all = "camelCase")]
-        struct Req {
-            signing_pub_key: String,
+        struct Sig {
+            action: &'static str,
             drone_pub_key: String,
-            unyt_pub_key: String,
-            metrics: Vec<ReqMetric>,
-            signature: String,
             timestamp: u64,
         }
 
+        let sig = serde_json::to_string(&Sig {
+            action: "list-dnas",
+            drone_pub_key: drone_pub_key.clone(),
+            timestamp: signature_timestamp,
+        })?;
+
+        let drone_signature = config.rt_drone_sec_key.sign(sig.as_bytes())?;
+
+        let res = self
+            .send(self.client.post(url).json(&Req {
+                drone_pub_key,
+                drone_signature,
+                signature_timestamp,
+            }))
+            .await?;
+
+        #[derive(serde::Deserialize)]
+        struct Res {
+            #[serde(default)]
+            registrations: Vec<DnaRegistration>,
+        }
+
+        let res: Res = serde_json::from_value(success_json(res).await?)?;
+
+        Ok(res.registrations)
+    }
+
+    /// Submit metrics to the endpoint.
+    pub async fn metrics(
+        &self,
+        config: &RuntimeConfigFile,
+        proofs: Vec<String>,
+    ) -> Result<Option<Receipt>> {
+        let signed =
+            SignedMetrics::new(config, proofs, self.signing_timestamp()?)?;
+        self.submit_metrics(&signed).await
+    }
+
+    /// Submit a signed metrics batch to the endpoint. A batch the collector
+    /// reports as already received is treated as success, so retrying a
+    /// batch whose response was lost is safe.
+    ///
+    /// If a collector public key is set, the acknowledgement must carry a
+    /// receipt for the batch signed by it, which is returned.
+    pub async fn submit_metrics(
+        &self,
+        signed: &SignedMetrics,
+    ) -> Result<Option<Receipt>> {
+        let mut url = self.url.clone();
+        url.set_path("/metrics");
+
+        let compression = self.request_compression();
+
+        let mut res =
+            self.post_metrics(url.clone(), signed, compression).await?;
+
+        if compression != RequestCompression::Identity
+            && res.status() == reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE
+        {
+            tracing::warn!(
+                "collector rejected {} request bodies, falling back to identity",
+                compression.as_str(),
+            );
+            *self.accept_encodings.lock().unwrap() = Some(Vec::new());
+            res = self
+                .post_metrics(url, signed, RequestCompression::Identity)
+                .await?;
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Res {
+            #[serde(default)]
+            success: bool,
+            #[serde(default)]
+            duplicate: bool,
+            #[serde(default)]
+            already_received: bool,
+            #[serde(default)]
+            batch_id: Option<String>,
+            #[serde(default)]
+            receipt: Option<Receipt>,
+        }
+
+        let res: Res = if res.status() == reqwest::StatusCode::CONFLICT {
+            // only a conflict confirming this very batch means it was
+            // delivered before, any other conflict is an error
+            let body = res.text().await.map_err(std::io::Error::other)?;
+            match serde_json::from_str::<Res>(&body) {
+                Ok(res)
+                    if res.already_received
+                        && res.batch_id.as_ref() == Some(&signed.batch_id) =>
+                {
+                    res
+                }
+                _ => {
+                    return Err(std::io::Error::other(format!(
+                        "conflict submitting batch {}: {body}",
+                        signed.batch_id,
+                    )));
+                }
+            }
+        } else if res.error_for_status_ref().is_err() {
+            return Err(std::io::Error::other(
+                res.text().await.map_err(std::io::Error::other)?,
+            ));
+        } else {
+            res.json().await.map_err(std::io::Error::other)?
+        };
+
+        if !res.success && !res.duplicate && !res.already_received {
+            return Err(std::io::Error::other(format!(
+                "invalid response: {res:?}"
+            )));
+        }
+
+        if !res.success {
+            // whatever the status, only a confirmation of this very batch
+            // means it was delivered before
+            if res.batch_id.as_ref() != Some(&signed.batch_id) {
+                return Err(std::io::Error::other(format!(
+                    "collector reported batch {:?} as already received, \
+                    expected {}",
+                    res.batch_id, signed.batch_id,
+                )));
+            }
+            tracing::debug!(batch_id = %signed.batch_id, "batch already received");
+        }
+
+        let Some(collector_pub_key) = &self.collector_pub_key else {
+            return Ok(None);
+        };
+
+        let receipt = res.receipt.ok_or_else(|| {
+            std::io::Error::other(format!(
+                "collector acknowledged batch {} without a receipt",
+                signed.batch_id,
+            ))
+        })?;
+        receipt.verify(collector_pub_key, &signed.batch_id)?;
+
+        Ok(Some(receipt))
+    }
+
+    async fn post_metrics(
+        &self,
+        url: reqwest::Url,
+        signed: &SignedMetrics,
+        compression: RequestCompression,
+    ) -> Result<reqwest::Response> {
+        let mut req = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(BATCH_ID_HEADER, &signed.batch_id);
+
+        if compression != RequestCompression::Identity {
+            req = req.header(
+                reqwest::header::CONTENT_ENCODING,
+                compression.as_str(),
+            );
+        }
+
+        let body = compression.encode(signed.body.as_bytes()).await?;
+
+        self.send(req.body(body)).await
+    }
+}
+
+/// Build a rustls config that trusts the system roots plus `ca_certs`, and
+/// additionally requires the server key to match one of `pins`.
+fn pinned_tls_config(
+    ca_certs: &[Vec<u8>],
+    identity: Option<&(Vec<u8>, Vec<u8>)>,
+    pins: Vec<String>,
+) -> Result<rustls::ClientConfig> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use std::sync::Arc;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut roots = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for err in native.errors {
+        tracing::warn!(?err, "failed to load system root certificates");
+    }
+    roots.add_parsable_certificates(native.certs);
+    for pem in ca_certs {
+        for cert in CertificateDer::pem_slice_iter(pem) {
+            roots
+                .add(cert.map_err(std::io::Error::other)?)
+                .map_err(std::io::Error::other)?;
+        }
+    }
+
+    let inner = rustls::client::WebPkiServerVerifier::builder_with_provider(
+        Arc::new(roots),
+        provider.clone(),
+    )
+    .build()
+    .map_err(std::io::Error::other)?;
+
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(std::io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+            inner,
+            pins,
+        }));
+
+    Ok(match identity {
+        Some((cert, key)) => {
+            let cert = CertificateDer::pem_slice_iter(cert)
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(std::io::Error::other)?;
+            let key = PrivateKeyDer::from_pem_slice(key)
+                .map_err(std::io::Error::other)?;
+            builder
+                .with_client_auth_cert(cert, key)
+                .map_err(std::io::Error::other)?
+        }
+        None => builder.with_no_client_auth(),
+    })
+}
+
+/// The usual webpki verification, followed by a check of the server key
+/// against the configured pins.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: std::sync::Arc<rustls::client::WebPkiServerVerifier>,
+    pins: Vec<String>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        ocsp_response: &[u8],
+        now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<
+        rustls::client::danger::ServerCertVerified,
+        rustls::Error,
+    > {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let hash = spki_sha256(end_entity)
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+
+        if !self.pins.contains(&hash) {
+            return Err(rustls::Error::General(format!(
+                "collector public key sha256//{hash} does not match any pin"
+            )));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<
+        rustls::client::danger::HandshakeSignatureValid,
+        rustls::Error,
+    > {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<
+        rustls::client::danger::HandshakeSignatureValid,
+        rustls::Error,
+    > {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Check the status of a response, and that its json body has
+/// `"success": true`.
+async fn success_json(res: reqwest::Response) -> Result<serde_json::Value> {
+    if res.error_for_status_ref().is_err() {
+        return Err(std::io::Error::other(
+            res.text().await.map_err(std::io::Error::other)?,
+        ));
+    }
+
+    let res: serde_json::Value =
+        res.json().await.map_err(std::io::Error::other)?;
+
+    if let Some(obj) = res.as_object()
+        && let Some(p) = obj.get("success")
+        && let Some(b) = p.as_bool()
+        && b
+    {
+        return Ok(res);
+    }
+
+    Err(std::io::Error::other(format!("invalid response: {res:?}")))
+}
+
+/// Parse a `Retry-After` header, either delay-seconds or an http date.
+fn retry_after(res: &reqwest::Response) -> Option<std::time::Duration> {
+    let value = res.headers().get(reqwest::header::RETRY_AFTER)?;
+    let value = value.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(std::time::Duration::from_secs(secs));
+    }
+
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(
+        at.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+/// A DNA registered for a drone.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnaRegistration {
+    /// The registered dna hash.
+    pub dna_hash: String,
+
+    /// The agreement the dna is registered under.
+    pub agreement_id: String,
+
+    /// The attached price sheet hash, if any.
+    #[serde(default)]
+    pub price_sheet_hash: Option<String>,
+
+    /// Additional json metadata, if any.
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Error code of a 404 response for a drone the collector doesn't know.
+pub const UNKNOWN_DRONE_CODE: &str = "unknownDrone";
+
+/// Header carrying the batch id of a metrics request.
+pub const BATCH_ID_HEADER: &str = "X-Batch-Id";
+
+/// A signed "/metrics" request body, ready to be submitted.
+#[derive(Debug, Clone)]
+pub struct SignedMetrics {
+    /// Deterministic id of the batch, derived from the drone public key and
+    /// the content of the proofs. Identical batches get identical ids, even
+    /// when re-signed.
+    pub batch_id: String,
+
+    /// The signature timestamp, in unix milliseconds.
+    pub timestamp: u64,
+
+    /// The json request body.
+    pub body: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReqMetric {
+    value: u64,
+    timestamp: u64,
+    #[serde(rename = "registered_unit_index")]
+    registered_unit_index: u64,
+    proof: String,
+    proof_hash: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Req {
+    signing_pub_key: String,
+    drone_pub_key: String,
+    unyt_pub_key: String,
+    batch_id: String,
+    metrics: Vec<ReqMetric>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replay_id: Option<String>,
+    signature: String,
+    timestamp: u64,
+}
+
+impl SignedMetrics {
+    /// Sign a batch of proofs. Each metric is stamped with a successive
+    /// millisecond after `timestamp`, see [Client::signing_timestamp].
+    pub fn new(
+        config: &RuntimeConfigFile,
+        proofs: Vec<String>,
+        timestamp: u64,
+    ) -> Result<Self> {
+        Self::with_replay_id(config, proofs, timestamp, None)
+    }
+
+    /// Sign a batch of proofs, as re-sent by the replay `replay_id` if
+    /// set. The replay id is part of the signed payload and the batch id,
+    /// see [replay_batch_id].
+    pub fn with_replay_id(
+        config: &RuntimeConfigFile,
+        proofs: Vec<String>,
+        mut timestamp: u64,
+        replay_id: Option<&str>,
+    ) -> Result<Self> {
         #[derive(serde::Serialize)]
         #[serde(rename_all = "camelCase")]
         struct Sig {
+            batch_id: String,
             drone_pub_key: String,
             metrics: Vec<ReqMetric>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            replay_id: Option<String>,
             signing_pub_key: String,
             timestamp: u64,
             unyt_pub_key: String,
         }
 
-        let mut timestamp = std::time::SystemTime::UNIX_EPOCH
-            .elapsed()
-            .expect("can get time")
-            .as_millis() as u64;
+        let batch_id = match replay_id {
+            Some(replay_id) => {
+                replay_batch_id(&config.drone_pub_key, replay_id, &proofs)
+            }
+            None => batch_id(&config.drone_pub_key, &proofs),
+        };
 
         let metrics: Vec<ReqMetric> = proofs
             .into_iter()
@@ -277,14 +1285,17 @@ impl Client {
                     value: 0,
                     timestamp,
                     registered_unit_index: 0,
+                    proof_hash: proof_hash(&proof),
                     proof,
                 }
             })
             .collect();
 
         let sig = serde_json::to_string(&Sig {
+            batch_id: batch_id.clone(),
             drone_pub_key: config.drone_pub_key.clone(),
             metrics: metrics.clone(),
+            replay_id: replay_id.map(String::from),
             signing_pub_key: config.drone_pub_key.clone(),
             timestamp,
             unyt_pub_key: config.unyt_pub_key.clone(),
@@ -292,38 +1303,107 @@ impl Client {
 
         let signature = config.rt_drone_sec_key.sign(sig.as_bytes())?;
 
-        let res = self
-            .client
-            .post(url)
-            .json(&Req {
-                signing_pub_key: config.drone_pub_key.clone(),
-                drone_pub_key: config.drone_pub_key.clone(),
-                unyt_pub_key: config.unyt_pub_key.clone(),
-                metrics,
-                signature,
-                timestamp,
-            })
-            .send()
-            .await
-            .map_err(std::io::Error::other)?;
+        let body = serde_json::to_string(&Req {
+            signing_pub_key: config.drone_pub_key.clone(),
+            drone_pub_key: config.drone_pub_key.clone(),
+            unyt_pub_key: config.unyt_pub_key.clone(),
+            batch_id: batch_id.clone(),
+            metrics,
+            replay_id: replay_id.map(String::from),
+            signature,
+            timestamp,
+        })?;
 
-        if res.error_for_status_ref().is_err() {
-            return Err(std::io::Error::other(
-                res.text().await.map_err(std::io::Error::other)?,
-            ));
-        }
+        Ok(Self {
+            batch_id,
+            timestamp,
+            body,
+        })
+    }
 
-        #[derive(Debug, serde::Deserialize)]
-        struct Res {
-            success: bool,
+    /// Wrap a previously signed request body, e.g. read from a bundle.
+    pub fn from_body(body: String) -> Result<Self> {
+        #[derive(serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Body {
+            batch_id: String,
+            timestamp: u64,
         }
 
-        let res: Res = res.json().await.map_err(std::io::Error::other)?;
+        let Body {
+            batch_id,
+            timestamp,
+        } = serde_json::from_str(&body).map_err(std::io::Error::other)?;
 
-        if res.success {
-            return Ok(());
-        }
+        Ok(Self {
+            batch_id,
+            timestamp,
+            body,
+        })
+    }
+}
 
-        Err(std::io::Error::other(format!("invalid response: {res:?}")))
+/// Computes the serialized size of "/metrics" request bodies without
+/// signing them, so batches can be kept within a byte budget.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSizer {
+    base: usize,
+}
+
+impl MetricsSizer {
+    /// Construct a sizer for requests signed by this drone.
+    pub fn new(config: &RuntimeConfig) -> Result<Self> {
+        Self::with_replay_id(config, None)
+    }
+
+    /// Construct a sizer for requests signed by this drone as part of the
+    /// replay `replay_id`, see [SignedMetrics::with_replay_id].
+    pub fn with_replay_id(
+        config: &RuntimeConfig,
+        replay_id: Option<&str>,
+    ) -> Result<Self> {
+        let signature_len =
+            PubKey::decode(config.drone_pub_key.as_bytes())?.signature_len();
+
+        let base = serde_json::to_string(&Req {
+            signing_pub_key: config.drone_pub_key.clone(),
+            drone_pub_key: config.drone_pub_key.clone(),
+            unyt_pub_key: config.unyt_pub_key.clone(),
+            batch_id: batch_id(&config.drone_pub_key, &[]),
+            metrics: Vec::new(),
+            replay_id: replay_id.map(String::from),
+            signature: "0".repeat(signature_len),
+            timestamp: Self::timestamp(),
+        })?
+        .len();
+
+        Ok(Self { base })
+    }
+
+    /// Size of a request containing no proofs.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Bytes a single proof adds to a request, including its separator.
+    pub fn proof_size(&self, proof: &str) -> usize {
+        let metric = ReqMetric {
+            value: 0,
+            timestamp: Self::timestamp(),
+            registered_unit_index: 0,
+            proof: proof.into(),
+            proof_hash: proof_hash(proof),
+        };
+
+        serde_json::to_string(&metric)
+            .map(|s| s.len() + 1)
+            .unwrap_or(proof.len())
+    }
+
+    fn timestamp() -> u64 {
+        std::time::SystemTime::UNIX_EPOCH
+            .elapsed()
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(u64::MAX)
     }
 }
@@ -0,0 +1,516 @@
+//! Reporting to one or more log-collector endpoints.
+
+use crate::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How proofs are distributed across multiple collector endpoints.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum EndpointMode {
+    /// Send each batch to the first healthy endpoint, in configured order.
+    #[default]
+    Failover,
+
+    /// Send each batch to every endpoint, e.g. to dual-write during a
+    /// migration.
+    FanOut,
+}
+
+impl std::str::FromStr for EndpointMode {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "failover" => Ok(Self::Failover),
+            "fanOut" | "fan-out" => Ok(Self::FanOut),
+            _ => Err(std::io::Error::other(format!(
+                "invalid endpoint mode: {s}"
+            ))),
+        }
+    }
+}
+
+/// When a batch sent to multiple endpoints counts as accepted, advancing
+/// the checkpoint.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum AckPolicy {
+    /// Accepted once any endpoint has accepted it. Endpoints that failed
+    /// are not sent the batch again, so they miss the batches the others
+    /// accepted.
+    Any,
+
+    /// Accepted once every endpoint has accepted it. Endpoints that
+    /// already accepted a retried batch treat it as a duplicate.
+    #[default]
+    All,
+}
+
+impl std::str::FromStr for AckPolicy {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "any" => Ok(Self::Any),
+            "all" => Ok(Self::All),
+            _ => Err(std::io::Error::other(format!("invalid ack policy: {s}"))),
+        }
+    }
+}
+
+/// An additional log-collector endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtraEndpoint {
+    /// Log collector endpoint.
+    pub endpoint: String,
+
+    /// Drone id at this endpoint, used to deregister from it. Zero until
+    /// the drone is registered, which the service does on startup. Metrics
+    /// identify the drone by its public key, and don't need the id.
+    #[serde(default)]
+    pub drone_id: u64,
+
+    /// Public key of the collector at this endpoint, base64 SPKI DER. If
+    /// set, every metrics batch it accepts must come with a receipt signed
+    /// by this key.
+    #[serde(default)]
+    pub collector_pub_key: Option<String>,
+}
+
+/// The outcome of a call to one endpoint.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointResult<T> {
+    /// The endpoint url.
+    pub endpoint: String,
+
+    /// The response, if the call succeeded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<T>,
+
+    /// Why the call failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<T> EndpointResult<T> {
+    fn new(endpoint: &str, res: Result<T>) -> Self {
+        let (response, error) = match res {
+            Ok(response) => (Some(response), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        Self {
+            endpoint: endpoint.to_string(),
+            response,
+            error,
+        }
+    }
+
+    /// Whether the call succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Additional endpoints, and how they are used alongside the primary one.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointConfig {
+    /// Endpoints used in addition to the primary `endpoint`.
+    #[serde(default)]
+    pub extra_endpoints: Vec<ExtraEndpoint>,
+
+    /// How batches are distributed across endpoints.
+    #[serde(default)]
+    pub endpoint_mode: EndpointMode,
+
+    /// In fan-out mode, when a batch counts as accepted.
+    #[serde(default)]
+    pub ack_policy: AckPolicy,
+}
+
+struct Endpoint {
+    url: String,
+    client: Client,
+    healthy: AtomicBool,
+}
+
+impl Endpoint {
+    /// Sign a batch for this endpoint, correcting for its clock skew.
+    fn sign(
+        &self,
+        config: &RuntimeConfigFile,
+        proofs: &[String],
+        replay_id: Option<&str>,
+    ) -> Result<SignedMetrics> {
+        SignedMetrics::with_replay_id(
+            config,
+            proofs.to_vec(),
+            self.client.signing_timestamp()?,
+            replay_id,
+        )
+    }
+
+    fn receipts(&self, receipt: Option<Receipt>) -> Vec<StoredReceipt> {
+        receipt
+            .map(|receipt| StoredReceipt {
+                endpoint: self.url.clone(),
+                receipt,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn set_healthy(&self, healthy: bool, err: Option<&std::io::Error>) {
+        let was = self.healthy.swap(healthy, Ordering::Relaxed);
+        match (was, healthy) {
+            (true, false) => tracing::warn!(
+                endpoint = %self.url,
+                ?err,
+                "collector endpoint is unhealthy",
+            ),
+            (false, true) => tracing::info!(
+                endpoint = %self.url,
+                "collector endpoint is healthy",
+            ),
+            _ => (),
+        }
+    }
+}
+
+/// Clients for the primary and any extra collector endpoints.
+pub struct Collectors {
+    endpoints: Vec<Endpoint>,
+    mode: EndpointMode,
+    ack_policy: AckPolicy,
+    replay_id: Option<String>,
+}
+
+impl Collectors {
+    /// Construct clients for every endpoint in the config.
+    pub async fn new(config: &RuntimeConfig) -> Result<Self> {
+        let mut endpoints = Vec::new();
+
+        let primary = (&config.endpoint, &config.collector_pub_key);
+        let extra = config
+            .endpoints
+            .extra_endpoints
+            .iter()
+            .map(|e| (&e.endpoint, &e.collector_pub_key));
+
+        for (url, collector_pub_key) in std::iter::once(primary).chain(extra) {
+            let parsed =
+                reqwest::Url::parse(url).map_err(std::io::Error::other)?;
+            let mut client = Client::with_config(parsed, &config.http).await?;
+            client.set_request_compression(config.request_compression);
+            if let Some(key) = collector_pub_key {
+                client.set_collector_pub_key(Some(PubKey::decode(
+                    key.as_bytes(),
+                )?));
+            }
+            client.set_clock_skew(
+                config.clock_skew_warn_seconds,
+                config.correct_clock_skew,
+            );
+            client.set_rate_limit(
+                config.rate_limit_requests_per_second,
+                config.rate_limit_bytes_per_second,
+            );
+            endpoints.push(Endpoint {
+                url: url.clone(),
+                client,
+                healthy: AtomicBool::new(true),
+            });
+        }
+
+        Ok(Self {
+            endpoints,
+            mode: config.endpoints.endpoint_mode,
+            ack_policy: config.endpoints.ack_policy,
+            replay_id: None,
+        })
+    }
+
+    /// Sign metrics as re-sent by the replay `replay_id`, so they get
+    /// their own batch ids, see [SignedMetrics::with_replay_id].
+    pub fn set_replay_id(&mut self, replay_id: Option<String>) {
+        self.replay_id = replay_id;
+    }
+
+    /// The client for the primary endpoint.
+    pub fn primary(&self) -> &Client {
+        &self.endpoints[0].client
+    }
+
+    /// Make a "health" call to every endpoint. Succeeds if any endpoint
+    /// is healthy.
+    pub async fn health(&self) -> Result<()> {
+        let results = futures_util::future::join_all(
+            self.endpoints.iter().map(|e| e.client.health()),
+        )
+        .await;
+
+        let mut first_err = None;
+        for (endpoint, res) in self.endpoints.iter().zip(results) {
+            match res {
+                Ok(()) => endpoint.set_healthy(true, None),
+                Err(err) => {
+                    endpoint.set_healthy(false, Some(&err));
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+
+        match first_err {
+            Some(err) if !self.any_healthy() => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Register the drone with any extra endpoint that has no drone id yet.
+    pub async fn register_drones(
+        &self,
+        config: &mut RuntimeConfigFile,
+    ) -> Result<()> {
+        for idx in 0..config.endpoints.extra_endpoints.len() {
+            if config.endpoints.extra_endpoints[idx].drone_id != 0 {
+                continue;
+            }
+            let endpoint = &self.endpoints[idx + 1];
+            let id = endpoint.client.drone_registration(config).await?;
+            tracing::info!(endpoint = %endpoint.url, id, "registered drone");
+            config.endpoints.extra_endpoints[idx].drone_id = id;
+            config.write().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deregister the drone from every endpoint it is registered with.
+    pub async fn deregister(&self, config: &RuntimeConfigFile) -> Result<()> {
+        let ids = std::iter::once(config.drone_id)
+            .chain(config.endpoints.extra_endpoints.iter().map(|e| e.drone_id));
+
+        for (endpoint, drone_id) in self.endpoints.iter().zip(ids) {
+            if drone_id == 0 {
+                continue;
+            }
+            endpoint.client.deregister(config, drone_id).await.map_err(
+                |err| std::io::Error::other(format!("{}: {err}", endpoint.url)),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Make a "register-dna" call to every endpoint, reporting the outcome
+    /// for each.
+    pub async fn register_dna(
+        &self,
+        config: &RuntimeConfigFile,
+        dna_hash: String,
+        agreement_id: String,
+        price_sheet_hash: Option<String>,
+        metadata: Option<serde_json::Value>,
+    ) -> Vec<EndpointResult<serde_json::Value>> {
+        self.each(|client| {
+            client.register_dna(
+                config,
+                dna_hash.clone(),
+                agreement_id.clone(),
+                price_sheet_hash.clone(),
+                metadata.clone(),
+            )
+        })
+        .await
+    }
+
+    /// Make an "update-dna" call to every endpoint, reporting the outcome
+    /// for each.
+    pub async fn update_dna(
+        &self,
+        config: &RuntimeConfigFile,
+        dna_hash: String,
+        agreement_id: String,
+        price_sheet_hash: Option<String>,
+        metadata: Option<serde_json::Value>,
+    ) -> Vec<EndpointResult<serde_json::Value>> {
+        self.each(|client| {
+            client.update_dna(
+                config,
+                dna_hash.clone(),
+                agreement_id.clone(),
+                price_sheet_hash.clone(),
+                metadata.clone(),
+            )
+        })
+        .await
+    }
+
+    /// Make an "unregister-dna" call to every endpoint, reporting the
+    /// outcome for each.
+    pub async fn unregister_dna(
+        &self,
+        config: &RuntimeConfigFile,
+        dna_hash: String,
+        agreement_id: String,
+    ) -> Vec<EndpointResult<serde_json::Value>> {
+        self.each(|client| {
+            client.unregister_dna(
+                config,
+                dna_hash.clone(),
+                agreement_id.clone(),
+            )
+        })
+        .await
+    }
+
+    /// Make a "list-dnas" call to every endpoint, reporting the
+    /// registrations of each.
+    pub async fn list_dnas(
+        &self,
+        config: &RuntimeConfigFile,
+    ) -> Vec<EndpointResult<Vec<DnaRegistration>>> {
+        self.each(|client| client.list_dnas(config)).await
+    }
+
+    /// The url and client of every endpoint, primary first.
+    pub fn clients(&self) -> impl Iterator<Item = (&str, &Client)> {
+        self.endpoints.iter().map(|e| (e.url.as_str(), &e.client))
+    }
+
+    /// Make a call to every endpoint in turn. A failure doesn't stop the
+    /// calls to the other endpoints, so the outcome of each is known.
+    async fn each<'a, F, Fut, T>(&'a self, f: F) -> Vec<EndpointResult<T>>
+    where
+        F: Fn(&'a Client) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut out = Vec::with_capacity(self.endpoints.len());
+
+        for endpoint in self.endpoints.iter() {
+            let res = f(&endpoint.client).await;
+            if let Err(err) = &res {
+                tracing::warn!(endpoint = %endpoint.url, ?err, "call failed");
+            }
+            out.push(EndpointResult::new(&endpoint.url, res));
+        }
+
+        out
+    }
+
+    /// Sign and submit metrics according to the endpoint mode. Each
+    /// endpoint's request is signed against that endpoint's clock. Any
+    /// collector receipts are stored in the config's receipt file.
+    pub async fn metrics(
+        &self,
+        config: &RuntimeConfigFile,
+        proofs: Vec<String>,
+    ) -> Result<()> {
+        let receipts = match self.mode {
+            EndpointMode::Failover => self.failover(config, &proofs).await?,
+            EndpointMode::FanOut => self.fan_out(config, &proofs).await?,
+        };
+
+        store_receipts(&config.receipt_path(), &receipts).await
+    }
+
+    /// Try healthy endpoints first, in order, then the unhealthy ones in
+    /// case they have recovered.
+    async fn failover(
+        &self,
+        config: &RuntimeConfigFile,
+        proofs: &[String],
+    ) -> Result<Vec<StoredReceipt>> {
+        let mut order: Vec<&Endpoint> = self.endpoints.iter().collect();
+        order.sort_by_key(|e| !e.healthy.load(Ordering::Relaxed));
+
+        let mut last_err = None;
+        for endpoint in order {
+            let signed =
+                endpoint.sign(config, proofs, self.replay_id.as_deref())?;
+            match endpoint.client.submit_metrics(&signed).await {
+                Ok(receipt) => {
+                    endpoint.set_healthy(true, None);
+                    return Ok(endpoint.receipts(receipt));
+                }
+                Err(err) => {
+                    endpoint.set_healthy(false, Some(&err));
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| std::io::Error::other("no endpoints")))
+    }
+
+    async fn fan_out(
+        &self,
+        config: &RuntimeConfigFile,
+        proofs: &[String],
+    ) -> Result<Vec<StoredReceipt>> {
+        let results = futures_util::future::join_all(
+            self.endpoints.iter().map(|e| async move {
+                let signed =
+                    e.sign(config, proofs, self.replay_id.as_deref())?;
+                e.client.submit_metrics(&signed).await
+            }),
+        )
+        .await;
+
+        let mut accepted = 0;
+        let mut receipts = Vec::new();
+        let mut errors = Vec::new();
+        for (endpoint, res) in self.endpoints.iter().zip(results) {
+            match res {
+                Ok(receipt) => {
+                    endpoint.set_healthy(true, None);
+                    accepted += 1;
+                    receipts.extend(endpoint.receipts(receipt));
+                }
+                Err(err) => {
+                    endpoint.set_healthy(false, Some(&err));
+                    errors.push(format!("{}: {err}", endpoint.url));
+                }
+            }
+        }
+
+        let ok = match self.ack_policy {
+            AckPolicy::Any => accepted > 0,
+            AckPolicy::All => errors.is_empty(),
+        };
+
+        if ok {
+            return Ok(receipts);
+        }
+
+        Err(std::io::Error::other(errors.join("; ")))
+    }
+
+    fn any_healthy(&self) -> bool {
+        self.endpoints
+            .iter()
+            .any(|e| e.healthy.load(Ordering::Relaxed))
+    }
+}
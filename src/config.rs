@@ -21,17 +21,177 @@ pub struct RuntimeConfig {
     /// Drone id.
     pub drone_id: u64,
 
+    /// Public key of the collector at `endpoint`, base64 SPKI DER. If set,
+    /// every metrics batch it accepts must come with a receipt signed by
+    /// this key.
+    #[serde(default)]
+    pub collector_pub_key: Option<String>,
+
+    /// Additional collector endpoints.
+    #[serde(flatten)]
+    pub endpoints: EndpointConfig,
+
     /// Report interval seconds.
     pub report_interval_seconds: u64,
 
-    /// List of paths from which to pull reports.
+    /// List of paths from which to pull reports. Each entry is either a
+    /// directory containing `*.jsonl` files, or a glob pattern such as
+    /// `/var/log/holochain/**/hc-report*.jsonl*`.
     pub report_path_list: Vec<std::path::PathBuf>,
 
+    /// Glob patterns of report files to skip.
+    #[serde(default)]
+    pub report_exclude_list: Vec<String>,
+
+    /// Maximum directory depth searched below the literal prefix of a
+    /// report path glob pattern. Unlimited if not set.
+    #[serde(default)]
+    pub report_max_depth: Option<usize>,
+
+    /// Report lines longer than this many bytes are discarded.
+    #[serde(default = "default_report_max_line_bytes")]
+    pub report_max_line_bytes: usize,
+
+    /// File that malformed report lines are appended to, along with their
+    /// source file and line number. Defaults to the config file path with a
+    /// `.dead-letter.jsonl` suffix.
+    #[serde(default)]
+    pub dead_letter_path: Option<std::path::PathBuf>,
+
+    /// Send a signed "parseErrors" summary metric to the collector when
+    /// malformed report lines are found.
+    #[serde(default)]
+    pub report_parse_errors: bool,
+
+    /// Number of acknowledged proof hashes remembered to drop duplicate
+    /// report lines, e.g. from copied or restored report files. Zero
+    /// disables deduplication.
+    #[serde(default = "default_dedup_window")]
+    pub dedup_window: usize,
+
+    /// Where acknowledged proof hashes are stored. Defaults to the config
+    /// file path with a `.proof-hashes` suffix.
+    #[serde(default)]
+    pub dedup_path: Option<std::path::PathBuf>,
+
+    /// Maximum number of proofs sent in a single metrics request.
+    #[serde(default = "default_batch_max_proofs")]
+    pub batch_max_proofs: usize,
+
+    /// Maximum size in bytes of a single metrics request body, including
+    /// the signature.
+    #[serde(default = "default_batch_max_bytes")]
+    pub batch_max_bytes: usize,
+
+    /// Content encoding for metrics request bodies. Only used if the
+    /// collector advertises support for it in its health response.
+    #[serde(default)]
+    pub request_compression: RequestCompression,
+
+    /// Maximum number of metrics requests in flight while catching up on
+    /// a backlog.
+    #[serde(default = "default_upload_concurrency")]
+    pub upload_concurrency: usize,
+
+    /// Maximum requests per second sent to the collector. Zero means
+    /// unlimited.
+    #[serde(default)]
+    pub rate_limit_requests_per_second: f64,
+
+    /// Maximum request body bytes per second sent to the collector. Zero
+    /// means unlimited.
+    #[serde(default)]
+    pub rate_limit_bytes_per_second: u64,
+
+    /// Clock skew against the collector, in seconds, above which a warning
+    /// is logged.
+    #[serde(default = "default_clock_skew_warn_seconds")]
+    pub clock_skew_warn_seconds: u64,
+
+    /// Shift signature timestamps by the measured clock skew against the
+    /// collector.
+    #[serde(default)]
+    pub correct_clock_skew: bool,
+
+    /// Http settings for connecting to the collector.
+    #[serde(flatten)]
+    pub http: HttpConfig,
+
+    /// File that verified receipts are appended to. Defaults to the config
+    /// file path with a `.receipts` suffix.
+    #[serde(default)]
+    pub receipt_path: Option<std::path::PathBuf>,
+
+    /// Maximum size in bytes of serialized DNA registration metadata.
+    #[serde(default = "default_dna_metadata_max_bytes")]
+    pub dna_metadata_max_bytes: usize,
+
+    /// Optional JSON schema file that DNA registration metadata must match.
+    #[serde(default)]
+    pub dna_metadata_schema_path: Option<std::path::PathBuf>,
+
     /// List of conductor config paths, for pulling db size reports.
     pub conductor_config_path_list: Vec<std::path::PathBuf>,
 
-    /// Last record timestamp sent.
-    pub last_record_timestamp: String,
+    /// Progress through the report files.
+    #[serde(flatten)]
+    pub report_checkpoint: ReportCheckpoint,
+
+    /// Minimum absolute change in bytes before a db size is re-reported.
+    /// Zero disables the absolute threshold.
+    #[serde(default)]
+    pub db_size_threshold_bytes: u64,
+
+    /// Minimum relative change in percent before a db size is re-reported.
+    /// Zero disables the percentage threshold. If both thresholds are
+    /// disabled, any change is reported.
+    #[serde(default)]
+    pub db_size_threshold_percent: f64,
+
+    /// Hours between forced full db size snapshots, so the collector can
+    /// distinguish "unchanged" from "not reporting".
+    #[serde(default = "default_db_size_heartbeat_hours")]
+    pub db_size_heartbeat_hours: u64,
+
+    /// How database sizes are measured.
+    #[serde(default)]
+    pub db_size_mode: DbSizeMode,
+
+    /// Db sizes as last acknowledged by the collector.
+    #[serde(default)]
+    pub db_size_state: DbSizeState,
+}
+
+fn default_report_max_line_bytes() -> usize {
+    DEFAULT_MAX_LINE_BYTES
+}
+
+fn default_batch_max_proofs() -> usize {
+    DEFAULT_BATCH_MAX_PROOFS
+}
+
+fn default_batch_max_bytes() -> usize {
+    DEFAULT_BATCH_MAX_BYTES
+}
+
+fn default_upload_concurrency() -> usize {
+    DEFAULT_UPLOAD_CONCURRENCY
+}
+
+fn default_clock_skew_warn_seconds() -> u64 {
+    DEFAULT_CLOCK_SKEW_WARN_SECONDS
+}
+
+fn default_dna_metadata_max_bytes() -> usize {
+    DEFAULT_DNA_METADATA_MAX_BYTES
+}
+
+fn default_dedup_window() -> usize {
+    100_000
+}
+
+fn default_db_size_heartbeat_hours() -> u64 {
+    6
 }
 
 impl RuntimeConfig {
@@ -53,10 +213,70 @@ impl RuntimeConfig {
             drone_sec_key,
             unyt_pub_key,
             drone_id,
+            collector_pub_key: None,
+            endpoints: EndpointConfig::default(),
             report_interval_seconds,
             report_path_list,
+            report_exclude_list: Vec::new(),
+            report_max_depth: None,
+            report_max_line_bytes: default_report_max_line_bytes(),
+            dead_letter_path: None,
+            report_parse_errors: false,
+            dedup_window: default_dedup_window(),
+            dedup_path: None,
+            batch_max_proofs: default_batch_max_proofs(),
+            batch_max_bytes: default_batch_max_bytes(),
+            request_compression: RequestCompression::default(),
+            upload_concurrency: default_upload_concurrency(),
+            rate_limit_requests_per_second: 0.0,
+            rate_limit_bytes_per_second: 0,
+            clock_skew_warn_seconds: default_clock_skew_warn_seconds(),
+            correct_clock_skew: false,
+            http: HttpConfig::default(),
+            receipt_path: None,
+            dna_metadata_max_bytes: default_dna_metadata_max_bytes(),
+            dna_metadata_schema_path: None,
             conductor_config_path_list,
-            last_record_timestamp: "0".into(),
+            report_checkpoint: ReportCheckpoint::new(),
+            db_size_threshold_bytes: 0,
+            db_size_threshold_percent: 0.0,
+            db_size_heartbeat_hours: default_db_size_heartbeat_hours(),
+            db_size_mode: DbSizeMode::default(),
+            db_size_state: DbSizeState::default(),
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// Read a runtime config from disk without locking it. The service may
+    /// be rewriting the file concurrently, so this retries briefly on
+    /// a partial read.
+    pub async fn load_unlocked(file: &std::path::Path) -> Result<Self> {
+        let mut last_err = None;
+        for _ in 0..10 {
+            let data = tokio::fs::read_to_string(file).await?;
+            match serde_json::from_str(&data) {
+                Ok(config) => return Ok(config),
+                Err(err) => last_err = Some(err),
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        Err(last_err.expect("at least one attempt").into())
+    }
+
+    /// Path of the file malformed report lines are appended to, for this
+    /// config stored at `config_file`.
+    pub fn dead_letter_path_for(
+        &self,
+        config_file: &std::path::Path,
+    ) -> std::path::PathBuf {
+        match &self.dead_letter_path {
+            Some(path) => path.clone(),
+            None => {
+                let mut path = config_file.to_path_buf().into_os_string();
+                path.push(".dead-letter.jsonl");
+                path.into()
+            }
         }
     }
 }
@@ -101,7 +321,7 @@ impl RuntimeConfigFile {
         conductor_config_path_list: Vec<std::path::PathBuf>,
     ) -> Result<Self> {
         let (rt_drone_pub_key, mut rt_drone_sec_key) =
-            generate_keypair().await?;
+            generate_drone_keypair().await?;
         rt_drone_sec_key = rt_drone_sec_key.precompute().await?;
 
         let path = file.clone();
@@ -178,6 +398,86 @@ impl RuntimeConfigFile {
         &self.path
     }
 
+    /// Get the path of the acknowledged proof hash store.
+    pub fn proof_hash_path(&self) -> std::path::PathBuf {
+        match &self.config.dedup_path {
+            Some(path) => path.clone(),
+            None => {
+                let mut path = self.path.clone().into_os_string();
+                path.push(".proof-hashes");
+                path.into()
+            }
+        }
+    }
+
+    /// Path of the file malformed report lines are appended to.
+    pub fn dead_letter_path(&self) -> std::path::PathBuf {
+        self.config.dead_letter_path_for(&self.path)
+    }
+
+    /// Path of the file collector receipts are stored in.
+    pub fn receipt_path(&self) -> std::path::PathBuf {
+        match &self.config.receipt_path {
+            Some(path) => path.clone(),
+            None => {
+                let mut path = self.path.clone().into_os_string();
+                path.push(".receipts");
+                path.into()
+            }
+        }
+    }
+
+    /// Path of the file an interrupted replay resumes from.
+    pub fn replay_state_path(&self) -> std::path::PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".replay");
+        path.into()
+    }
+
+    /// Write a copy of the config, including the drone secret key, to a
+    /// new file at `dest`. On unix the copy is only readable by its owner.
+    pub async fn archive(&self, dest: &std::path::Path) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let data = serde_json::to_string_pretty(&self.config)?;
+
+        let mut opts = tokio::fs::OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        opts.mode(0o600);
+
+        let mut file = opts.open(dest).await?;
+        file.write_all(data.as_bytes()).await?;
+        file.sync_all().await?;
+        Ok(())
+    }
+
+    /// Overwrite the config file, including the drone secret key, with
+    /// random data, then delete it. Filesystems that don't overwrite in
+    /// place (copy-on-write, some SSD firmware) may still retain the old
+    /// contents, so this is a best effort.
+    pub async fn shred(self) -> Result<()> {
+        use rand::RngCore;
+        use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+        let Self { mut file, path, .. } = self;
+
+        let mut len = file.metadata().await?.len() as usize;
+        let mut buf = vec![0; 4096];
+        file.rewind().await?;
+        while len > 0 {
+            let n = len.min(buf.len());
+            rand::thread_rng().fill_bytes(&mut buf[..n]);
+            file.write_all(&buf[..n]).await?;
+            len -= n;
+        }
+        file.sync_all().await?;
+
+        // release the lock before removing, windows won't delete open files
+        drop(file);
+        tokio::fs::remove_file(path).await
+    }
+
     /// Write the config to the file.
     pub async fn write(&mut self) -> Result<()> {
         use tokio::io::{AsyncSeekExt, AsyncWriteExt};
@@ -4,9 +4,46 @@ use super::*;
 use base64::prelude::*;
 
 /// Public key.
+#[derive(Clone)]
 pub struct PubKey(rsa::RsaPublicKey);
 
 impl PubKey {
+    /// Decode a public key in base64 SPKI DER format.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        use rsa::pkcs8::DecodePublicKey;
+        Ok(Self(
+            rsa::RsaPublicKey::from_public_key_der(
+                &BASE64_STANDARD
+                    .decode(data)
+                    .map_err(std::io::Error::other)?,
+            )
+            .map_err(std::io::Error::other)?,
+        ))
+    }
+
+    /// Length of a base64 encoded signature made with the matching
+    /// secret key.
+    pub fn signature_len(&self) -> usize {
+        use rsa::traits::PublicKeyParts;
+        self.0.size().div_ceil(3) * 4
+    }
+
+    /// Verify a base64 signature made with [SecKey::sign].
+    pub fn verify(&self, data: &[u8], signature: &str) -> Result<()> {
+        use rsa::sha2::Digest;
+        let digest = rsa::sha2::Sha256::digest(data);
+        let signature = BASE64_STANDARD
+            .decode(signature)
+            .map_err(std::io::Error::other)?;
+        self.0
+            .verify(
+                rsa::pss::Pss::new::<rsa::sha2::Sha256>(),
+                &digest,
+                &signature,
+            )
+            .map_err(|_| std::io::Error::other("invalid signature"))
+    }
+
     /// Encode the public key in SPKI DER format for compatibility with log-collector.
     pub fn encode(&self) -> Result<String> {
         use rsa::pkcs8::EncodePublicKey;
@@ -21,6 +58,7 @@ impl PubKey {
 }
 
 /// Secret key.
+#[derive(Clone)]
 pub struct SecKey(rsa::RsaPrivateKey);
 
 impl SecKey {
@@ -71,6 +109,58 @@ impl SecKey {
     }
 }
 
+/// Hex-encoded SHA-256 hash of a proof, used to identify it across
+/// restarts and copies of the same report line.
+pub fn proof_hash(proof: &str) -> String {
+    sha256_hex(proof.as_bytes())
+}
+
+/// Hex-encoded deterministic id of a batch of proofs, derived from the
+/// drone public key and the hashes of the proofs in order.
+pub fn batch_id(drone_pub_key: &str, proofs: &[String]) -> String {
+    let mut content = String::from(drone_pub_key);
+    for proof in proofs {
+        content.push('\n');
+        content.push_str(&proof_hash(proof));
+    }
+    sha256_hex(content.as_bytes())
+}
+
+/// Id of a batch re-sent by a replay. The replay id is mixed in, so the
+/// collector doesn't mistake a replayed batch for the original delivery.
+pub fn replay_batch_id(
+    drone_pub_key: &str,
+    replay_id: &str,
+    proofs: &[String],
+) -> String {
+    batch_id(&format!("{drone_pub_key}\nreplay {replay_id}"), proofs)
+}
+
+/// Base64 SHA-256 of the subject public key info of a DER certificate,
+/// as used for certificate pinning (e.g. curl's `sha256//` pins).
+pub fn spki_sha256(cert_der: &[u8]) -> Result<String> {
+    use rsa::sha2::Digest;
+    use x509_cert::der::{Decode, Encode};
+
+    let cert = x509_cert::Certificate::from_der(cert_der)
+        .map_err(std::io::Error::other)?;
+    let spki = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(std::io::Error::other)?;
+
+    Ok(BASE64_STANDARD.encode(rsa::sha2::Sha256::digest(&spki)))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use rsa::sha2::Digest;
+    rsa::sha2::Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
 /// Generate a keypair.
 pub async fn generate_keypair() -> Result<(PubKey, SecKey)> {
     tokio::task::spawn_blocking(|| {
@@ -81,3 +171,17 @@ pub async fn generate_keypair() -> Result<(PubKey, SecKey)> {
     })
     .await?
 }
+
+/// Generate a new drone keypair. Tests share a single keypair, as
+/// generating RSA keys is slow in debug builds.
+pub(crate) async fn generate_drone_keypair() -> Result<(PubKey, SecKey)> {
+    #[cfg(test)]
+    {
+        static KEYPAIR: tokio::sync::OnceCell<(PubKey, SecKey)> =
+            tokio::sync::OnceCell::const_new();
+        KEYPAIR.get_or_try_init(generate_keypair).await.cloned()
+    }
+
+    #[cfg(not(test))]
+    generate_keypair().await
+}
@@ -1,7 +1,7 @@
 //! Check database sizes.
 
 use crate::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "k", rename_all = "camelCase")]
@@ -13,39 +13,216 @@ enum ReportEntry {
         #[serde(rename = "d")]
         space: String,
 
+        // in accurate mode, this is the on-disk size
         #[serde(rename = "b")]
         total_bytes: String,
+
+        #[serde(rename = "l", skip_serializing_if = "Option::is_none")]
+        logical_bytes: Option<String>,
+
+        #[serde(rename = "o", skip_serializing_if = "Option::is_none")]
+        on_disk_bytes: Option<String>,
+
+        #[serde(rename = "w", skip_serializing_if = "Option::is_none")]
+        wal_bytes: Option<String>,
     },
 }
 
-/// Check database sizes.
-pub async fn check_db_size(config: &RuntimeConfig) -> Result<Vec<String>> {
-    let mut out = Vec::new();
+/// How database sizes are measured.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum DbSizeMode {
+    /// Sum the file lengths of each database and its `-wal` / `-shm` files.
+    #[default]
+    Simple,
 
-    for conductor in config.conductor_config_path_list.iter() {
-        let conductor = tokio::fs::read_to_string(&conductor).await?;
+    /// Read the logical size from the SQLite header and the on-disk size
+    /// from allocated blocks, reporting the WAL size separately.
+    ///
+    /// In this mode the usual total (`b`) is the on-disk size, i.e. the
+    /// allocated blocks of the database and its `-wal` / `-shm` files,
+    /// rather than their summed lengths. The logical size is reported as
+    /// `l`, the on-disk size also as `o`, and the WAL length as `w`.
+    Accurate,
+}
 
-        #[derive(Debug, serde::Deserialize)]
-        struct C {
-            data_root_path: std::path::PathBuf,
-        }
+/// Measured size of a single space.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SpaceSize {
+    /// Summed file lengths, or the on-disk size in accurate mode.
+    total_bytes: u64,
+    accurate: Option<AccurateSize>,
+}
 
-        let conductor: C =
-            serde_yaml::from_str(&conductor).map_err(std::io::Error::other)?;
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct AccurateSize {
+    logical_bytes: u64,
+    on_disk_bytes: u64,
+    wal_bytes: u64,
+}
+
+/// Db size reporting state, used to suppress unchanged sizes.
+#[derive(
+    Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DbSizeState {
+    /// Last reported size in bytes per space, by conductor config path.
+    /// Conductors may host the same space, so each is tracked separately.
+    #[serde(default)]
+    pub last_reported: BTreeMap<std::path::PathBuf, BTreeMap<String, u64>>,
+
+    /// Microsecond timestamp of the last full snapshot.
+    #[serde(default)]
+    pub last_snapshot: u64,
+}
+
+/// Check database sizes. Returns the proofs that should be reported,
+/// along with the state to store once they have been acknowledged.
+pub async fn check_db_size(
+    config: &RuntimeConfig,
+) -> Result<(Vec<String>, DbSizeState)> {
+    let mut sizes = Vec::new();
+
+    for conductor in config.conductor_config_path_list.iter() {
+        let data_root_path = conductor_data_root_path(conductor).await?;
 
         // only check dht database for now... it's gossipy : )
-        let db_dir = conductor.data_root_path.join("databases").join("dht");
+        let db_dir = data_root_path.join("databases").join("dht");
 
         tracing::trace!(?db_dir);
 
-        out.append(&mut get_sizes(&db_dir).await?);
+        sizes.push((
+            conductor.clone(),
+            match config.db_size_mode {
+                DbSizeMode::Simple => get_sizes(&db_dir).await?,
+                DbSizeMode::Accurate => get_accurate_sizes(&db_dir).await?,
+            },
+        ));
+    }
+
+    let now = unix_time()?.as_micros() as u64;
+
+    let heartbeat = config
+        .db_size_heartbeat_hours
+        .saturating_mul(60 * 60 * 1_000_000);
+    let snapshot =
+        now.saturating_sub(config.db_size_state.last_snapshot) >= heartbeat;
+
+    let mut state = DbSizeState {
+        last_reported: BTreeMap::new(),
+        last_snapshot: if snapshot {
+            now
+        } else {
+            config.db_size_state.last_snapshot
+        },
+    };
+
+    let mut out = Vec::new();
+
+    for (conductor, spaces) in sizes {
+        let last_reported = config.db_size_state.last_reported.get(&conductor);
+        let reported = state.last_reported.entry(conductor).or_default();
+
+        for (space, size) in spaces {
+            let total_bytes = size.total_bytes;
+
+            let prev = last_reported.and_then(|l| l.get(&space)).copied();
+
+            let report = match prev {
+                _ if snapshot => true,
+                None => true,
+                Some(prev) => is_significant(config, prev, total_bytes),
+            };
+
+            if !report {
+                // keep comparing against what the collector last saw
+                reported.insert(space, prev.unwrap_or_default());
+                continue;
+            }
+
+            out.push(
+                serde_json::to_string(&ReportEntry::DbSize {
+                    timestamp: now.to_string(),
+                    space: space.clone(),
+                    total_bytes: total_bytes.to_string(),
+                    logical_bytes: size
+                        .accurate
+                        .map(|a| a.logical_bytes.to_string()),
+                    on_disk_bytes: size
+                        .accurate
+                        .map(|a| a.on_disk_bytes.to_string()),
+                    wal_bytes: size.accurate.map(|a| a.wal_bytes.to_string()),
+                })
+                .map_err(std::io::Error::other)?,
+            );
+
+            reported.insert(space, total_bytes);
+        }
+    }
+
+    Ok((out, state))
+}
+
+/// Read the data root path out of a conductor config file.
+pub(crate) async fn conductor_data_root_path(
+    conductor: &std::path::Path,
+) -> Result<std::path::PathBuf> {
+    let conductor = tokio::fs::read_to_string(conductor).await?;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct C {
+        data_root_path: std::path::PathBuf,
+    }
+
+    let conductor: C =
+        serde_yaml::from_str(&conductor).map_err(std::io::Error::other)?;
+
+    Ok(conductor.data_root_path)
+}
+
+fn is_significant(config: &RuntimeConfig, prev: u64, cur: u64) -> bool {
+    let delta = prev.abs_diff(cur);
+
+    if delta == 0 {
+        return false;
+    }
+
+    let bytes = config.db_size_threshold_bytes;
+    let percent = config.db_size_threshold_percent;
+
+    if bytes == 0 && percent <= 0.0 {
+        return true;
+    }
+
+    if bytes > 0 && delta > bytes {
+        return true;
     }
 
-    Ok(out)
+    if percent > 0.0 {
+        // a space growing from nothing is always significant
+        if prev == 0 {
+            return true;
+        }
+        return delta as f64 * 100.0 / prev as f64 > percent;
+    }
+
+    false
 }
 
-async fn get_sizes(dir: &std::path::Path) -> Result<Vec<String>> {
-    let mut map: HashMap<String, u64> = HashMap::new();
+async fn get_sizes(
+    dir: &std::path::Path,
+) -> Result<HashMap<String, SpaceSize>> {
+    let mut map: HashMap<String, SpaceSize> = HashMap::new();
 
     let mut dir = tokio::fs::read_dir(dir).await?;
 
@@ -63,27 +240,112 @@ async fn get_sizes(dir: &std::path::Path) -> Result<Vec<String>> {
             .trim_end_matches("-wal")
             .to_string();
 
-        *map.entry(name).or_default() += meta.len();
+        map.entry(name).or_default().total_bytes += meta.len();
     }
 
-    let now = std::time::SystemTime::UNIX_EPOCH
-        .elapsed()
-        .expect("system time")
-        .as_micros()
-        .to_string();
-
-    let mut out = Vec::with_capacity(map.len());
-
-    for (k, v) in map {
-        out.push(
-            serde_json::to_string(&ReportEntry::DbSize {
-                timestamp: now.clone(),
-                space: k,
-                total_bytes: v.to_string(),
-            })
-            .map_err(std::io::Error::other)?,
-        );
+    Ok(map)
+}
+
+/// Group each database with exactly its own `-wal` and `-shm` files, and
+/// measure them individually.
+async fn get_accurate_sizes(
+    dir: &std::path::Path,
+) -> Result<HashMap<String, SpaceSize>> {
+    let mut map: HashMap<String, SpaceSize> = HashMap::new();
+
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+
+        let meta = entry.metadata().await?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let (space, kind) = if let Some(s) = name.strip_suffix("-wal") {
+            (s.to_string(), FileKind::Wal)
+        } else if let Some(s) = name.strip_suffix("-shm") {
+            (s.to_string(), FileKind::Shm)
+        } else {
+            (name, FileKind::Main)
+        };
+
+        let on_disk = allocated_bytes(&meta);
+
+        let size = map.entry(space).or_default();
+        let accurate = size.accurate.get_or_insert_default();
+        accurate.on_disk_bytes += on_disk;
+
+        match kind {
+            FileKind::Main => {
+                accurate.logical_bytes =
+                    sqlite_logical_size(&entry.path(), meta.len()).await?;
+            }
+            FileKind::Wal => accurate.wal_bytes = meta.len(),
+            FileKind::Shm => (),
+        }
+    }
+
+    for size in map.values_mut() {
+        if let Some(accurate) = size.accurate {
+            size.total_bytes = accurate.on_disk_bytes;
+        }
+    }
+
+    Ok(map)
+}
+
+enum FileKind {
+    Main,
+    Wal,
+    Shm,
+}
+
+#[cfg(unix)]
+fn allocated_bytes(meta: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks is always in 512-byte units
+    meta.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_bytes(meta: &std::fs::Metadata) -> u64 {
+    meta.len()
+}
+
+/// Read `page_size * page_count` out of an SQLite database header.
+/// Falls back to the file length for files that are not SQLite databases.
+async fn sqlite_logical_size(path: &std::path::Path, len: u64) -> Result<u64> {
+    use tokio::io::AsyncReadExt;
+
+    let mut header = [0; 100];
+    let mut file = tokio::fs::File::open(path).await?;
+    if file.read_exact(&mut header).await.is_err()
+        || &header[..16] != b"SQLite format 3\0"
+    {
+        return Ok(len);
+    }
+
+    let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        n => n as u64,
+    };
+
+    if page_size == 0 {
+        return Ok(len);
+    }
+
+    let page_count =
+        u32::from_be_bytes([header[28], header[29], header[30], header[31]]);
+    let change_counter = &header[24..28];
+    let version_valid_for = &header[92..96];
+
+    // the in-header page count is only valid if it was written by a
+    // version of sqlite that keeps it up to date
+    if page_count == 0 || change_counter != version_valid_for {
+        return Ok(len / page_size * page_size);
     }
 
-    Ok(out)
+    Ok(page_size * page_count as u64)
 }
@@ -0,0 +1,159 @@
+//! Persistent deduplication of proofs that have already been accepted.
+
+use super::*;
+use std::collections::{HashSet, VecDeque};
+
+/// A bounded, persistent set of the hashes of proofs that the collector has
+/// acknowledged. Only the most recent `window` hashes are remembered.
+pub struct ProofDedup {
+    path: std::path::PathBuf,
+    window: usize,
+    set: HashSet<String>,
+    order: VecDeque<String>,
+    file: Option<tokio::fs::File>,
+    file_lines: usize,
+}
+
+impl ProofDedup {
+    /// Load the hash store from disk, creating it if it does not exist.
+    /// A `window` of zero disables deduplication.
+    pub async fn load(path: std::path::PathBuf, window: usize) -> Result<Self> {
+        let mut this = Self {
+            path,
+            window,
+            set: HashSet::new(),
+            order: VecDeque::new(),
+            file: None,
+            file_lines: 0,
+        };
+
+        if window == 0 {
+            return Ok(this);
+        }
+
+        match tokio::fs::read_to_string(&this.path).await {
+            Ok(data) => {
+                for hash in data.lines() {
+                    this.file_lines += 1;
+                    this.insert(hash.to_string());
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+
+        this.file = Some(
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&this.path)
+                .await?,
+        );
+
+        Ok(this)
+    }
+
+    /// Number of hashes currently remembered.
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// True if no hashes are remembered.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Drop proofs that have already been acknowledged, as well as repeats
+    /// within the list itself.
+    pub fn filter(&self, proofs: Vec<String>) -> Vec<String> {
+        if self.window == 0 {
+            return proofs;
+        }
+
+        let mut seen = HashSet::new();
+        let total = proofs.len();
+
+        let out: Vec<String> = proofs
+            .into_iter()
+            .filter(|proof| {
+                let hash = proof_hash(proof);
+                !self.set.contains(&hash) && seen.insert(hash)
+            })
+            .collect();
+
+        if out.len() < total {
+            tracing::debug!(
+                "Dropped {} already acknowledged proofs",
+                total - out.len(),
+            );
+        }
+
+        out
+    }
+
+    /// Remember proofs that have been acknowledged by the collector.
+    pub async fn record(&mut self, proofs: &[String]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        if self.window == 0 {
+            return Ok(());
+        }
+
+        let mut data = String::new();
+        for proof in proofs {
+            let hash = proof_hash(proof);
+            data.push_str(&hash);
+            data.push('\n');
+            self.insert(hash);
+        }
+
+        if let Some(file) = &mut self.file {
+            file.write_all(data.as_bytes()).await?;
+            file.flush().await?;
+        }
+        self.file_lines += proofs.len();
+
+        if self.file_lines > self.window * 2 {
+            self.compact().await?;
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, hash: String) {
+        if !self.set.insert(hash.clone()) {
+            return;
+        }
+        self.order.push_back(hash);
+        while self.order.len() > self.window {
+            if let Some(old) = self.order.pop_front() {
+                self.set.remove(&old);
+            }
+        }
+    }
+
+    /// Rewrite the store with only the hashes still in the window.
+    async fn compact(&mut self) -> Result<()> {
+        let mut data = String::new();
+        for hash in self.order.iter() {
+            data.push_str(hash);
+            data.push('\n');
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, data).await?;
+        self.file = None;
+        tokio::fs::rename(&tmp, &self.path).await?;
+
+        self.file = Some(
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&self.path)
+                .await?,
+        );
+        self.file_lines = self.order.len();
+
+        Ok(())
+    }
+}
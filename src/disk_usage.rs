@@ -0,0 +1,130 @@
+//! Check filesystem usage of conductor data volumes.
+
+use crate::*;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "k", rename_all = "camelCase")]
+enum ReportEntry {
+    DiskUsage {
+        #[serde(rename = "t")]
+        timestamp: String,
+
+        #[serde(rename = "p")]
+        path: String,
+
+        #[serde(rename = "b")]
+        total_bytes: String,
+
+        #[serde(rename = "u")]
+        used_bytes: String,
+
+        #[serde(rename = "a")]
+        available_bytes: String,
+
+        #[serde(rename = "i")]
+        total_inodes: String,
+
+        #[serde(rename = "f")]
+        free_inodes: String,
+    },
+}
+
+/// Filesystem statistics for a single path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    /// Total size of the filesystem in bytes.
+    pub total_bytes: u64,
+
+    /// Bytes in use.
+    pub used_bytes: u64,
+
+    /// Bytes available to unprivileged users.
+    pub available_bytes: u64,
+
+    /// Total inode count.
+    pub total_inodes: u64,
+
+    /// Free inode count.
+    pub free_inodes: u64,
+}
+
+/// Check the filesystem holding each conductor's data root path.
+pub async fn check_disk_usage(config: &RuntimeConfig) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+
+    let now = unix_time()?.as_micros().to_string();
+
+    for conductor in config.conductor_config_path_list.iter() {
+        let data_root_path = conductor_data_root_path(conductor).await?;
+
+        tracing::trace!(?data_root_path);
+
+        let stats = match fs_stats(data_root_path.clone()).await? {
+            Some(stats) => stats,
+            None => {
+                tracing::debug!("Disk usage not supported on this platform");
+                return Ok(out);
+            }
+        };
+
+        out.push(
+            serde_json::to_string(&ReportEntry::DiskUsage {
+                timestamp: now.clone(),
+                path: data_root_path.to_string_lossy().to_string(),
+                total_bytes: stats.total_bytes.to_string(),
+                used_bytes: stats.used_bytes.to_string(),
+                available_bytes: stats.available_bytes.to_string(),
+                total_inodes: stats.total_inodes.to_string(),
+                free_inodes: stats.free_inodes.to_string(),
+            })
+            .map_err(std::io::Error::other)?,
+        );
+    }
+
+    Ok(out)
+}
+
+/// Get filesystem statistics for the volume containing `path`.
+/// Returns `None` on platforms without `statvfs`.
+pub async fn fs_stats(path: std::path::PathBuf) -> Result<Option<FsStats>> {
+    tokio::task::spawn_blocking(move || statvfs(&path)).await?
+}
+
+#[cfg(unix)]
+fn statvfs(path: &std::path::Path) -> Result<Option<FsStats>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(std::io::Error::other)?;
+
+    // safety: statvfs is plain old data, zeroed is a valid bit pattern
+    let mut s: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // safety: path is a valid nul-terminated string, s is a valid pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut s) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    let (frsize, blocks, bfree, bavail, files, ffree) = (
+        s.f_frsize as u64,
+        s.f_blocks as u64,
+        s.f_bfree as u64,
+        s.f_bavail as u64,
+        s.f_files as u64,
+        s.f_ffree as u64,
+    );
+
+    Ok(Some(FsStats {
+        total_bytes: blocks * frsize,
+        used_bytes: blocks.saturating_sub(bfree) * frsize,
+        available_bytes: bavail * frsize,
+        total_inodes: files,
+        free_inodes: ffree,
+    }))
+}
+
+#[cfg(not(unix))]
+fn statvfs(_path: &std::path::Path) -> Result<Option<FsStats>> {
+    Ok(None)
+}
@@ -0,0 +1,382 @@
+//! Bulk management of DNA registrations, either from a manifest or by
+//! syncing with the DNAs hosted by the conductors.
+
+use crate::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Agreement terms to register a DNA under.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DnaAgreement {
+    /// The agreement id to register.
+    pub agreement_id: String,
+
+    /// Optional price-sheet hash.
+    #[serde(default)]
+    pub price_sheet_hash: Option<String>,
+
+    /// Optional additional json metadata.
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Maps DNA hashes to the agreement they should be registered under. The
+/// key `*` applies to any hosted DNA not listed explicitly.
+pub type AgreementMap = BTreeMap<String, DnaAgreement>;
+
+/// Load an agreement mapping from a YAML or JSON file.
+pub async fn load_agreements(path: &std::path::Path) -> Result<AgreementMap> {
+    let data = tokio::fs::read_to_string(path).await?;
+    serde_yaml::from_str(&data).map_err(|err| {
+        std::io::Error::other(format!("{}: {err}", path.display()))
+    })
+}
+
+/// The DNA hashes hosted by each configured conductor, taken from the
+/// database file names in its `databases/dht` directory.
+pub async fn hosted_dnas(config: &RuntimeConfig) -> Result<BTreeSet<String>> {
+    let mut out = BTreeSet::new();
+
+    for conductor in config.conductor_config_path_list.iter() {
+        let data_root_path = conductor_data_root_path(conductor).await?;
+        let db_dir = data_root_path.join("databases").join("dht");
+
+        let mut dir = tokio::fs::read_dir(&db_dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().to_string();
+            let name = name.trim_end_matches("-shm").trim_end_matches("-wal");
+            let name = name.split('.').next().unwrap_or_default();
+
+            if name.starts_with(DNA_HASH_PREFIX) {
+                out.insert(name.to_string());
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Base64 prefix of a Holochain DNA hash.
+pub const DNA_HASH_PREFIX: &str = "uhC0k";
+
+/// A DNA that could not be registered.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncFailure {
+    /// The dna hash.
+    pub dna_hash: String,
+
+    /// Why the registration failed.
+    pub error: String,
+}
+
+/// The outcome of syncing DNA registrations.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    /// The endpoint the registrations were synced with.
+    pub endpoint: String,
+
+    /// Nothing was registered, `registered` lists what would have been.
+    pub dry_run: bool,
+
+    /// Hosted DNAs that were newly registered.
+    pub registered: Vec<String>,
+
+    /// Hosted DNAs that were already registered.
+    pub already_registered: Vec<String>,
+
+    /// Hosted DNAs with no entry in the agreement mapping.
+    pub missing_agreement: Vec<String>,
+
+    /// Registered DNAs not hosted by any configured conductor.
+    pub not_hosted: Vec<String>,
+
+    /// Hosted DNAs whose registration failed.
+    pub failed: Vec<SyncFailure>,
+
+    /// Why the endpoint's registrations could not be listed, in which case
+    /// nothing was synced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Register each hosted DNA that is not yet registered, under the
+/// agreement given by `agreements`. Each endpoint is checked on its own,
+/// so a re-run catches up an endpoint that missed registrations.
+pub async fn sync_registrations(
+    config: &RuntimeConfigFile,
+    collectors: &Collectors,
+    agreements: &AgreementMap,
+    dry_run: bool,
+) -> Result<Vec<SyncReport>> {
+    let hosted = hosted_dnas(config).await?;
+
+    let mut out = Vec::new();
+    for (endpoint, client) in collectors.clients() {
+        out.push(
+            sync_endpoint(
+                config, endpoint, client, &hosted, agreements, dry_run,
+            )
+            .await,
+        );
+    }
+
+    Ok(out)
+}
+
+async fn sync_endpoint(
+    config: &RuntimeConfigFile,
+    endpoint: &str,
+    client: &Client,
+    hosted: &BTreeSet<String>,
+    agreements: &AgreementMap,
+    dry_run: bool,
+) -> SyncReport {
+    let mut report = SyncReport {
+        endpoint: endpoint.to_string(),
+        dry_run,
+        ..Default::default()
+    };
+
+    let registered: BTreeSet<String> = match client.list_dnas(config).await {
+        Ok(registered) => registered.into_iter().map(|r| r.dna_hash).collect(),
+        Err(err) => {
+            tracing::warn!(%endpoint, ?err, "failed to list dnas");
+            report.error = Some(err.to_string());
+            return report;
+        }
+    };
+
+    report.not_hosted = registered.difference(hosted).cloned().collect();
+
+    for dna_hash in hosted.iter().cloned() {
+        if registered.contains(&dna_hash) {
+            report.already_registered.push(dna_hash);
+            continue;
+        }
+
+        let Some(agreement) =
+            agreements.get(&dna_hash).or_else(|| agreements.get("*"))
+        else {
+            tracing::warn!(%dna_hash, "hosted dna has no agreement");
+            report.missing_agreement.push(dna_hash);
+            continue;
+        };
+
+        if let Err(err) = validate_dna_registration(
+            config,
+            &dna_hash,
+            &agreement.agreement_id,
+            agreement.price_sheet_hash.as_deref(),
+            agreement.metadata.as_ref(),
+        )
+        .await
+        {
+            report.failed.push(SyncFailure {
+                dna_hash,
+                error: err.to_string(),
+            });
+            continue;
+        }
+
+        if dry_run {
+            report.registered.push(dna_hash);
+            continue;
+        }
+
+        match client
+            .register_dna(
+                config,
+                dna_hash.clone(),
+                agreement.agreement_id.clone(),
+                agreement.price_sheet_hash.clone(),
+                agreement.metadata.clone(),
+            )
+            .await
+        {
+            Ok(_) => {
+                tracing::info!(%endpoint, %dna_hash, "registered dna");
+                report.registered.push(dna_hash);
+            }
+            Err(err) => report.failed.push(SyncFailure {
+                dna_hash,
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    report
+}
+
+/// An entry in a DNA registration manifest.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    /// The dna hash to register.
+    pub dna_hash: String,
+
+    /// The agreement id to register.
+    pub agreement_id: String,
+
+    /// Optional price-sheet hash.
+    #[serde(default)]
+    pub price_sheet_hash: Option<String>,
+
+    /// Optional additional json metadata.
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Load a YAML or JSON manifest, either a list of entries or an object
+/// with a `dnas` list.
+pub async fn load_manifest(
+    path: &std::path::Path,
+) -> Result<Vec<ManifestEntry>> {
+    let data = tokio::fs::read_to_string(path).await?;
+    let err = |err: serde_yaml::Error| {
+        std::io::Error::other(format!("{}: {err}", path.display()))
+    };
+
+    let mut manifest: serde_yaml::Value =
+        serde_yaml::from_str(&data).map_err(err)?;
+    if let Some(dnas) = manifest.get_mut("dnas") {
+        manifest = std::mem::take(dnas);
+    }
+
+    serde_yaml::from_value(manifest).map_err(err)
+}
+
+/// What happened to a manifest entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ManifestStatus {
+    /// Newly registered.
+    Registered,
+
+    /// Already registered, with a different price sheet or metadata.
+    Updated,
+
+    /// Already registered as given.
+    Unchanged,
+
+    /// The call failed, see the error.
+    Failed,
+}
+
+/// The outcome of a single manifest entry.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestResult {
+    /// The endpoint the entry was registered with.
+    pub endpoint: String,
+
+    /// The dna hash.
+    pub dna_hash: String,
+
+    /// The agreement id.
+    pub agreement_id: String,
+
+    /// What happened.
+    pub status: ManifestStatus,
+
+    /// Why the call failed, if it did.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Register every manifest entry with every endpoint. Entries that are
+/// already registered with an endpoint are left alone, or updated if their
+/// price sheet or metadata changed, so re-running a manifest is safe and
+/// catches up an endpoint that missed entries. A failed entry doesn't stop
+/// the rest.
+pub async fn register_manifest(
+    config: &RuntimeConfigFile,
+    collectors: &Collectors,
+    manifest: Vec<ManifestEntry>,
+) -> Vec<ManifestResult> {
+    let mut out = Vec::with_capacity(manifest.len());
+
+    for (endpoint, client) in collectors.clients() {
+        let registered: BTreeMap<(String, String), DnaRegistration> =
+            match client.list_dnas(config).await {
+                Ok(registered) => registered
+                    .into_iter()
+                    .map(|r| ((r.dna_hash.clone(), r.agreement_id.clone()), r))
+                    .collect(),
+                Err(err) => {
+                    // without the current registrations nothing is safe
+                    // to send to this endpoint
+                    tracing::warn!(%endpoint, ?err, "failed to list dnas");
+                    out.extend(manifest.iter().map(|entry| ManifestResult {
+                        endpoint: endpoint.to_string(),
+                        dna_hash: entry.dna_hash.clone(),
+                        agreement_id: entry.agreement_id.clone(),
+                        status: ManifestStatus::Failed,
+                        error: Some(err.to_string()),
+                    }));
+                    continue;
+                }
+            };
+
+        for entry in manifest.iter().cloned() {
+            let key = (entry.dna_hash.clone(), entry.agreement_id.clone());
+
+            let res = match registered.get(&key) {
+                Some(r)
+                    if r.price_sheet_hash == entry.price_sheet_hash
+                        && r.metadata == entry.metadata =>
+                {
+                    Ok(ManifestStatus::Unchanged)
+                }
+                Some(_) => client
+                    .update_dna(
+                        config,
+                        entry.dna_hash.clone(),
+                        entry.agreement_id.clone(),
+                        entry.price_sheet_hash,
+                        entry.metadata,
+                    )
+                    .await
+                    .map(|_| ManifestStatus::Updated),
+                None => client
+                    .register_dna(
+                        config,
+                        entry.dna_hash.clone(),
+                        entry.agreement_id.clone(),
+                        entry.price_sheet_hash,
+                        entry.metadata,
+                    )
+                    .await
+                    .map(|_| ManifestStatus::Registered),
+            };
+
+            let (status, error) = match res {
+                Ok(status) => (status, None),
+                Err(err) => {
+                    tracing::warn!(
+                        %endpoint,
+                        dna_hash = %entry.dna_hash,
+                        ?err,
+                        "failed",
+                    );
+                    (ManifestStatus::Failed, Some(err.to_string()))
+                }
+            };
+
+            out.push(ManifestResult {
+                endpoint: endpoint.to_string(),
+                dna_hash: entry.dna_hash,
+                agreement_id: entry.agreement_id,
+                status,
+                error,
+            });
+        }
+    }
+
+    out
+}
@@ -18,16 +18,53 @@ use reader::*;
 pub mod db_size;
 use db_size::*;
 
+pub mod disk_usage;
+use disk_usage::*;
+
+pub mod dedup;
+use dedup::*;
+
+pub mod rate_limit;
+use rate_limit::*;
+
+pub mod collectors;
+use collectors::*;
+
+pub mod dna_sync;
+use dna_sync::*;
+
+pub mod validate;
+use validate::*;
+
+pub mod receipt;
+use receipt::*;
+
+pub mod bundle;
+use bundle::*;
+
+pub mod replay;
+use replay::*;
+
 /// Initialize a new log-sender configuration file.
+#[allow(clippy::too_many_arguments)]
 pub async fn initialize(
     config_file: std::path::PathBuf,
     endpoint: String,
+    collector_pub_key: Option<String>,
     unyt_pub_key: String,
     report_interval_seconds: u64,
     report_path_list: Vec<std::path::PathBuf>,
+    report_exclude_list: Vec<String>,
+    report_max_depth: Option<usize>,
     conductor_config_path_list: Vec<std::path::PathBuf>,
+    http: HttpConfig,
+    endpoints: EndpointConfig,
 ) -> Result<()> {
-    let url = reqwest::Url::parse(&endpoint).map_err(std::io::Error::other)?;
+    for url in std::iter::once(&endpoint)
+        .chain(endpoints.extra_endpoints.iter().map(|e| &e.endpoint))
+    {
+        reqwest::Url::parse(url).map_err(std::io::Error::other)?;
+    }
 
     let mut config = RuntimeConfigFile::with_init(
         config_file,
@@ -39,37 +76,42 @@ pub async fn initialize(
         conductor_config_path_list,
     )
     .await?;
+    config.collector_pub_key = collector_pub_key;
+    config.report_exclude_list = report_exclude_list;
+    config.report_max_depth = report_max_depth;
+    config.http = http;
+    config.endpoints = endpoints;
 
-    let client = Client::new(url).await?;
+    let collectors = Collectors::new(&config).await?;
 
-    client.health().await?;
+    collectors.health().await?;
 
-    let id = client.drone_registration(&config).await?;
+    let id = collectors.primary().drone_registration(&config).await?;
 
     config.drone_id = id;
     config.write().await?;
 
+    collectors.register_drones(&mut config).await?;
+
     Ok(())
 }
 
-/// Register DNA hashes with agreements and optional price sheets for a drone.
+/// Register DNA hashes with agreements and optional price sheets for a drone,
+/// with every endpoint, reporting the outcome for each.
 pub async fn register_dna(
     config_file: std::path::PathBuf,
     dna_hash: String,
     agreement_id: String,
     price_sheet_hash: Option<String>,
     metadata: Option<serde_json::Value>,
-) -> Result<serde_json::Value> {
+) -> Result<Vec<EndpointResult<serde_json::Value>>> {
     let config = RuntimeConfigFile::with_load(config_file).await?;
 
-    let url =
-        reqwest::Url::parse(&config.endpoint).map_err(std::io::Error::other)?;
+    let collectors = Collectors::new(&config).await?;
 
-    let client = Client::new(url).await?;
+    collectors.health().await?;
 
-    client.health().await?;
-
-    client
+    Ok(collectors
         .register_dna(
             &config,
             dna_hash,
@@ -77,45 +119,475 @@ pub async fn register_dna(
             price_sheet_hash,
             metadata,
         )
-        .await
+        .await)
+}
+
+/// Register every DNA listed in a YAML or JSON manifest file, reporting
+/// the outcome of each entry. Safe to re-run.
+pub async fn register_dnas(
+    config_file: std::path::PathBuf,
+    manifest_path: std::path::PathBuf,
+) -> Result<Vec<ManifestResult>> {
+    let manifest = load_manifest(&manifest_path).await?;
+
+    let config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let collectors = Collectors::new(&config).await?;
+
+    collectors.health().await?;
+
+    Ok(register_manifest(&config, &collectors, manifest).await)
+}
+
+/// Update the price sheet and metadata of a registered DNA with every
+/// endpoint, reporting the outcome for each.
+pub async fn update_dna(
+    config_file: std::path::PathBuf,
+    dna_hash: String,
+    agreement_id: String,
+    price_sheet_hash: Option<String>,
+    metadata: Option<serde_json::Value>,
+) -> Result<Vec<EndpointResult<serde_json::Value>>> {
+    let config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let collectors = Collectors::new(&config).await?;
+
+    collectors.health().await?;
+
+    Ok(collectors
+        .update_dna(&config, dna_hash, agreement_id, price_sheet_hash, metadata)
+        .await)
+}
+
+/// Remove a DNA registration from every endpoint, reporting the outcome
+/// for each.
+pub async fn unregister_dna(
+    config_file: std::path::PathBuf,
+    dna_hash: String,
+    agreement_id: String,
+) -> Result<Vec<EndpointResult<serde_json::Value>>> {
+    let config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let collectors = Collectors::new(&config).await?;
+
+    collectors.health().await?;
+
+    Ok(collectors
+        .unregister_dna(&config, dna_hash, agreement_id)
+        .await)
+}
+
+/// List the DNAs registered for a drone with each endpoint.
+pub async fn list_dnas(
+    config_file: std::path::PathBuf,
+) -> Result<Vec<EndpointResult<Vec<DnaRegistration>>>> {
+    let config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let collectors = Collectors::new(&config).await?;
+
+    collectors.health().await?;
+
+    Ok(collectors.list_dnas(&config).await)
+}
+
+/// Register the DNAs hosted by the configured conductors that are not yet
+/// registered with each endpoint, using the agreement mapping file at
+/// `agreements_path`.
+pub async fn sync_dnas(
+    config_file: std::path::PathBuf,
+    agreements_path: std::path::PathBuf,
+    dry_run: bool,
+) -> Result<Vec<SyncReport>> {
+    let agreements = load_agreements(&agreements_path).await?;
+
+    let config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let collectors = Collectors::new(&config).await?;
+
+    collectors.health().await?;
+
+    sync_registrations(&config, &collectors, &agreements, dry_run).await
+}
+
+/// Summarize the state of a drone without taking the config file lock,
+/// so it can be used while the service is running.
+pub async fn status(
+    config_file: std::path::PathBuf,
+) -> Result<serde_json::Value> {
+    let config = RuntimeConfig::load_unlocked(&config_file).await?;
+    let checkpoint = &config.report_checkpoint;
+
+    Ok(serde_json::json!({
+        "endpoint": config.endpoint,
+        "droneId": config.drone_id,
+        "extraEndpoints": config.endpoints.extra_endpoints,
+        "endpointMode": config.endpoints.endpoint_mode,
+        "dronePubKey": config.drone_pub_key,
+        "lastRecordTimestamp": checkpoint.last_record_timestamp,
+        "reportFiles": checkpoint.report_files.len(),
+        "parseErrors": checkpoint.parse_errors,
+        "deadLetterPath": config.dead_letter_path_for(&config_file),
+    }))
+}
+
+/// Retire a drone. Any unsent proofs are flushed first, then the drone is
+/// deregistered from every collector endpoint. Finally the config file,
+/// which holds the drone secret key, is optionally archived to `archive`
+/// and then shredded.
+pub async fn decommission(
+    config_file: std::path::PathBuf,
+    archive: Option<std::path::PathBuf>,
+) -> Result<()> {
+    let mut config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let collectors = Collectors::new(&config).await?;
+
+    collectors.health().await?;
+
+    let dedup = tokio::sync::Mutex::new(
+        ProofDedup::load(config.proof_hash_path(), config.dedup_window).await?,
+    );
+
+    tracing::info!("Flushing unsent proofs..");
+    let mut checkpoint = config.report_checkpoint.clone();
+    let res = send_reports(&config, &collectors, &dedup, &mut checkpoint).await;
+    config.report_checkpoint = checkpoint;
+    config.write().await?;
+    res?;
+
+    tracing::info!("Deregistering drone..");
+    collectors.deregister(&config).await?;
+
+    if let Some(archive) = archive {
+        tracing::info!("Archiving config to {}..", archive.display());
+        config.archive(&archive).await?;
+    }
+
+    let proof_hash_path = config.proof_hash_path();
+    config.shred().await?;
+
+    match tokio::fs::remove_file(proof_hash_path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Write signed metrics for new db sizes and report proofs to the bundle
+/// file at `out` instead of sending them, for hosts without network access.
+/// The bundle is appended to, and progress is recorded as if the proofs
+/// had been sent once it is safely on disk. Requests are signed at export
+/// time, and the collector may reject signatures that are too old by the
+/// time they are uploaded, so upload the bundle promptly. [upload] warns
+/// about requests older than [STALE_BUNDLE_SECONDS].
+pub async fn export(
+    config_file: std::path::PathBuf,
+    out: std::path::PathBuf,
+) -> Result<ExportReport> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let mut config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let bundle = BundleWriter::open(&out).await?;
+
+    let dedup =
+        ProofDedup::load(config.proof_hash_path(), config.dedup_window).await?;
+    let dedup = tokio::sync::Mutex::new(dedup);
+
+    let requests = AtomicUsize::new(0);
+    let proof_count = AtomicUsize::new(0);
+
+    // there is no collector to measure clock skew against
+    let write = async |proofs: Vec<String>| -> Result<()> {
+        let count = proofs.len();
+        let timestamp = unix_time()?.as_millis() as u64;
+        bundle
+            .write(&SignedMetrics::new(&config, proofs, timestamp)?)
+            .await?;
+        requests.fetch_add(1, Ordering::Relaxed);
+        proof_count.fetch_add(count, Ordering::Relaxed);
+        Ok(())
+    };
+
+    let (db_sizes, db_size_state) = check_db_size(&config).await?;
+    let mut proofs = db_sizes;
+    proofs.extend(check_disk_usage(&config).await?);
+    if !proofs.is_empty() {
+        write(proofs).await?;
+    }
+
+    let mut checkpoint = config.report_checkpoint.clone();
+    let res = read_reports(
+        &config.report_path_list,
+        &mut checkpoint,
+        &ReadOptions::from_config(&config)?,
+        |proofs| async {
+            let proofs = dedup.lock().await.filter(proofs);
+            if proofs.is_empty() {
+                return Ok(());
+            }
+            write(proofs.clone()).await?;
+            dedup.lock().await.record(&proofs).await
+        },
+    )
+    .await;
+
+    let mut parse_errors = checkpoint.parse_errors.clone();
+    if config.report_parse_errors
+        && let Some(proof) = parse_errors.summary_proof()?
+    {
+        write(vec![proof]).await?;
+        parse_errors.reported = parse_errors.total;
+    }
+
+    // only record progress for what is on disk
+    bundle.sync().await?;
+
+    config.db_size_state = db_size_state;
+    config.report_checkpoint = checkpoint;
+    config.report_checkpoint.parse_errors = parse_errors;
+    config.write().await?;
+
+    match res {
+        Err(err) if err.kind() != std::io::ErrorKind::WouldBlock => {
+            return Err(err);
+        }
+        _ => (),
+    }
+
+    Ok(ExportReport {
+        requests: requests.into_inner(),
+        proofs: proof_count.into_inner(),
+    })
+}
+
+/// Submit the signed requests in a bundle written by [export] to the
+/// collector at `endpoint`. The drone config is not needed. Requests the
+/// collector has already received are skipped, so an interrupted upload
+/// can simply be re-run. If `collector_pub_key` is set, every response must
+/// carry a receipt signed by it, and the receipts are appended to the file
+/// at [bundle_receipt_path].
+pub async fn upload(
+    endpoint: String,
+    bundle_path: std::path::PathBuf,
+    http: HttpConfig,
+    collector_pub_key: Option<String>,
+) -> Result<UploadReport> {
+    let bundle = read_bundle(&bundle_path).await?;
+
+    let mut client = Client::with_config(
+        reqwest::Url::parse(&endpoint).map_err(std::io::Error::other)?,
+        &http,
+    )
+    .await?;
+    client.set_collector_pub_key(
+        collector_pub_key
+            .map(|key| PubKey::decode(key.as_bytes()))
+            .transpose()?,
+    );
+
+    client.health().await?;
+
+    // judge the age by the collector's clock, if it told us the time
+    let now = (unix_time()?.as_millis() as i64)
+        .saturating_add(client.clock_skew_ms().unwrap_or(0));
+    if let Some(oldest) = bundle.iter().map(|s| s.timestamp).min() {
+        let age_seconds = (now - oldest as i64).max(0) as u64 / 1000;
+        if age_seconds > STALE_BUNDLE_SECONDS {
+            tracing::warn!(
+                "bundle requests were signed up to {:.1}h ago, the collector \
+                may reject them as stale, export a new bundle if so",
+                age_seconds as f64 / 3600.0,
+            );
+        }
+    }
+
+    let mut report = UploadReport {
+        requests: bundle.len(),
+        ..Default::default()
+    };
+
+    let mut receipts = Vec::new();
+    for signed in bundle.iter() {
+        match client.submit_metrics(signed).await {
+            Ok(receipt) => {
+                report.uploaded += 1;
+                receipts.extend(receipt.map(|receipt| StoredReceipt {
+                    endpoint: endpoint.clone(),
+                    receipt,
+                }));
+            }
+            Err(err) => {
+                report.error = Some(format!("{}: {err}", signed.batch_id));
+                break;
+            }
+        }
+        tracing::info!("Uploaded {}/{}..", report.uploaded, report.requests);
+    }
+
+    // keep the receipts of what was accepted, even if the upload stopped
+    store_receipts(&bundle_receipt_path(&bundle_path), &receipts).await?;
+    report.receipts = receipts.len();
+
+    Ok(report)
+}
+
+/// Re-send the report proofs of `kinds` with timestamps from `from` to
+/// `to`, inclusive, e.g. after collector data loss. The range is limited to
+/// what has already been sent, and the regular checkpoint is left alone.
+/// Progress is saved as batches are accepted, so re-running an interrupted
+/// replay with the same arguments resumes it. Replayed batches are signed
+/// with a replay id, so the collector doesn't skip them as duplicates of
+/// the original delivery.
+pub async fn replay(
+    config_file: std::path::PathBuf,
+    from: u64,
+    to: u64,
+    kinds: Vec<String>,
+) -> Result<ReplayReport> {
+    if from > to {
+        return Err(std::io::Error::other(format!(
+            "invalid range: {from} is after {to}"
+        )));
+    }
+
+    let kinds = match kinds.is_empty() {
+        true => vec![DEFAULT_REPORT_KIND.to_string()],
+        false => kinds,
+    };
+
+    let config = RuntimeConfigFile::with_load(config_file).await?;
+
+    let sent_until: u64 = config
+        .report_checkpoint
+        .last_record_timestamp
+        .parse()
+        .map_err(std::io::Error::other)?;
+    if to > sent_until {
+        tracing::warn!(
+            "limiting replay to {sent_until}, later proofs have not been sent"
+        );
+    }
+    let until = to.min(sent_until);
+
+    let state_path = config.replay_state_path();
+    let saved = ReplayState::resume(&state_path, from, to, &kinds).await?;
+    let resumed = saved.is_some();
+    let state = tokio::sync::Mutex::new(
+        saved.unwrap_or_else(|| ReplayState::new(from, to, kinds.clone())),
+    );
+
+    let replay_id = state.lock().await.replay_id.clone();
+
+    let mut collectors = Collectors::new(&config).await?;
+    collectors.set_replay_id(Some(replay_id.clone()));
+
+    collectors.health().await?;
+
+    let mut checkpoint = ReportCheckpoint::new();
+    checkpoint.last_record_timestamp =
+        state.lock().await.last_record_timestamp.to_string();
+
+    // one batch at a time, so saved progress never skips a batch
+    let opts = ReadOptions {
+        dead_letter_path: None,
+        upload_concurrency: 1,
+        kinds,
+        until: Some(until),
+        sizer: MetricsSizer::with_replay_id(&config, Some(&replay_id))?,
+        ..ReadOptions::from_config(&config)?
+    };
+
+    read_reports(&config.report_path_list, &mut checkpoint, &opts, |proofs| {
+        let (collectors, config) = (&collectors, &config);
+        let (state, state_path) = (&state, &state_path);
+        async move {
+            collectors.metrics(config, proofs.clone()).await?;
+            let mut state = state.lock().await;
+            state.record(&proofs)?;
+            state.save(state_path).await?;
+            tracing::info!(
+                "Replayed {} proofs, up to {} ({:.1}%)..",
+                state.proofs,
+                state.last_record_timestamp,
+                state.percent(),
+            );
+            Ok(())
+        }
+    })
+    .await?;
+
+    match tokio::fs::remove_file(&state_path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            return Err(err);
+        }
+        _ => (),
+    }
+
+    Ok(ReplayReport {
+        resumed,
+        to: until,
+        proofs: state.into_inner().proofs,
+    })
 }
 
 /// Run the service checking for report logs and reporting them.
 pub async fn run_service(config_file: std::path::PathBuf) -> Result<()> {
     let mut config = RuntimeConfigFile::with_load(config_file).await?;
 
-    let url =
-        reqwest::Url::parse(&config.endpoint).map_err(std::io::Error::other)?;
+    let collectors = Collectors::new(&config).await?;
 
-    let client = Client::new(url).await?;
+    collectors.health().await?;
 
-    client.health().await?;
+    let dedup = tokio::sync::Mutex::new(
+        ProofDedup::load(config.proof_hash_path(), config.dedup_window).await?,
+    );
 
     loop {
+        if let Err(err) = collectors.register_drones(&mut config).await {
+            eprintln!("Error registering with extra endpoints: {err:?}");
+        }
+
         tracing::debug!("Checking DB sizes..");
-        let db_sizes = check_db_size(&config).await?;
+        let (db_sizes, db_size_state) = check_db_size(&config).await?;
         tracing::debug!(?db_sizes);
 
-        tracing::info!("Reporting {} db size proofs..", db_sizes.len());
-        if let Err(err) = client.metrics(&config, db_sizes).await {
-            eprintln!("Error reporting db sizes: {err:?}");
+        tracing::debug!("Checking disk usage..");
+        let disk_usage = match check_disk_usage(&config).await {
+            Ok(disk_usage) => disk_usage,
+            Err(err) => {
+                eprintln!("Error checking disk usage: {err:?}");
+                Vec::new()
+            }
+        };
+        tracing::debug!(?disk_usage);
+
+        if db_sizes.is_empty() && disk_usage.is_empty() {
+            config.db_size_state = db_size_state;
+        } else {
+            tracing::info!(
+                "Reporting {} db size and {} disk usage proofs..",
+                db_sizes.len(),
+                disk_usage.len(),
+            );
+            let mut proofs = db_sizes;
+            proofs.extend(disk_usage);
+            match collectors.metrics(&config, proofs).await {
+                Ok(()) => config.db_size_state = db_size_state,
+                Err(err) => eprintln!("Error reporting db sizes: {err:?}"),
+            }
         }
 
         tracing::debug!("Running reports..");
-        match read_reports(
-            &config.report_path_list,
-            config.last_record_timestamp.clone(),
-            |proofs| async {
-                tracing::info!("Reporting {} proofs..", proofs.len());
-                client.metrics(&config, proofs).await
-            },
-        )
-        .await
-        {
-            Ok(timestamp) => {
-                config.last_record_timestamp = timestamp;
-                config.write().await?;
-            }
+        let mut checkpoint = config.report_checkpoint.clone();
+        let res =
+            send_reports(&config, &collectors, &dedup, &mut checkpoint).await;
+
+        // keep whatever progress was made, even on error
+        config.report_checkpoint = checkpoint;
+        config.write().await?;
+
+        match res {
+            Ok(()) => (),
             Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
                 // ignore, this is a non-fatal error
             }
@@ -124,6 +596,23 @@ pub async fn run_service(config_file: std::path::PathBuf) -> Result<()> {
             }
         }
 
+        if config.report_parse_errors
+            && let Some(proof) =
+                config.report_checkpoint.parse_errors.summary_proof()?
+        {
+            tracing::info!("Reporting parse error summary..");
+            match collectors.metrics(&config, vec![proof]).await {
+                Ok(()) => {
+                    let stats = &mut config.report_checkpoint.parse_errors;
+                    stats.reported = stats.total;
+                    config.write().await?;
+                }
+                Err(err) => {
+                    eprintln!("Error reporting parse errors: {err:?}")
+                }
+            }
+        }
+
         tracing::debug!("done.");
 
         tokio::time::sleep(std::time::Duration::from_secs(
@@ -133,5 +622,41 @@ pub async fn run_service(config_file: std::path::PathBuf) -> Result<()> {
     }
 }
 
+/// Send any new report proofs. Progress is recorded in `checkpoint`, even
+/// if sending fails part way through.
+async fn send_reports(
+    config: &RuntimeConfigFile,
+    collectors: &Collectors,
+    dedup: &tokio::sync::Mutex<ProofDedup>,
+    checkpoint: &mut ReportCheckpoint,
+) -> Result<()> {
+    read_reports(
+        &config.report_path_list,
+        checkpoint,
+        &ReadOptions::from_config(config)?,
+        |proofs| async {
+            // don't hold the lock across the request, other batches
+            // may be in flight
+            let proofs = dedup.lock().await.filter(proofs);
+            if proofs.is_empty() {
+                return Ok(());
+            }
+            tracing::info!("Reporting {} proofs..", proofs.len());
+            collectors.metrics(config, proofs.clone()).await?;
+            dedup.lock().await.record(&proofs).await
+        },
+    )
+    .await
+}
+
+/// Time since the unix epoch, per the local clock.
+pub(crate) fn unix_time() -> Result<std::time::Duration> {
+    std::time::SystemTime::UNIX_EPOCH.elapsed().map_err(|err| {
+        std::io::Error::other(format!(
+            "system clock is before the unix epoch: {err}"
+        ))
+    })
+}
+
 #[cfg(test)]
 mod test;
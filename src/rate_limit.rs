@@ -0,0 +1,136 @@
+//! Client-side request and bandwidth limits.
+
+use std::time::{Duration, Instant};
+
+/// Token-bucket limits on requests per second and bytes per second.
+///
+/// Each bucket holds up to one second's worth of tokens, so a drone can
+/// burst up to its configured rate and is then paced. Limits can be
+/// changed at any time, and the limiter can be paused outright, e.g. when
+/// the collector asks us to back off.
+pub struct RateLimiter {
+    inner: std::sync::Mutex<Inner>,
+}
+
+struct Inner {
+    requests: Bucket,
+    bytes: Bucket,
+    paused_until: Option<Instant>,
+}
+
+/// A single token bucket. A rate of zero disables it.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    /// How long until `n` tokens can be taken. Requests larger than the
+    /// bucket only wait for it to be full, and leave it in deficit.
+    fn wait(&self, n: f64) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let missing = n.min(self.rate) - self.tokens;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing / self.rate)
+    }
+
+    fn take(&mut self, n: f64) {
+        if self.rate > 0.0 {
+            self.tokens -= n;
+        }
+    }
+
+    fn set_rate(&mut self, rate: f64, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate);
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(0.0, 0)
+    }
+}
+
+impl RateLimiter {
+    /// Construct a limiter. Zero disables the respective limit.
+    pub fn new(requests_per_second: f64, bytes_per_second: u64) -> Self {
+        Self {
+            inner: std::sync::Mutex::new(Inner {
+                requests: Bucket::new(requests_per_second.max(0.0)),
+                bytes: Bucket::new(bytes_per_second as f64),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Change the limits. Zero disables the respective limit.
+    pub fn set_limits(&self, requests_per_second: f64, bytes_per_second: u64) {
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.requests.set_rate(requests_per_second.max(0.0), now);
+        inner.bytes.set_rate(bytes_per_second as f64, now);
+    }
+
+    /// Hold all requests for at least `duration`.
+    pub fn pause(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.paused_until.is_none_or(|cur| cur < until) {
+            inner.paused_until = Some(until);
+        }
+    }
+
+    /// Wait until a request of `bytes` may be sent.
+    pub async fn acquire(&self, bytes: usize) {
+        loop {
+            let wait = {
+                let now = Instant::now();
+                let mut inner = self.inner.lock().unwrap();
+                inner.requests.refill(now);
+                inner.bytes.refill(now);
+
+                let paused = inner
+                    .paused_until
+                    .map(|until| until.saturating_duration_since(now))
+                    .unwrap_or_default();
+
+                let wait = paused
+                    .max(inner.requests.wait(1.0))
+                    .max(inner.bytes.wait(bytes as f64));
+
+                if wait.is_zero() {
+                    inner.paused_until = None;
+                    inner.requests.take(1.0);
+                    inner.bytes.take(bytes as f64);
+                    return;
+                }
+
+                wait
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
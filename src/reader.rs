@@ -10,70 +10,1020 @@ struct Parse {
     t: String,
 }
 
-/// Read reports from disk. Returns the new time to ignore before.
-pub async fn read_reports<F, C>(
+/// Progress through the report files, persisted between runs.
+#[derive(
+    Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportCheckpoint {
+    /// Last record timestamp sent.
+    pub last_record_timestamp: String,
+
+    /// Per-file state of report files that have been read.
+    #[serde(default)]
+    pub report_files:
+        std::collections::BTreeMap<std::path::PathBuf, FileCheckpoint>,
+
+    /// Malformed report lines seen so far.
+    #[serde(default)]
+    pub parse_errors: ParseErrorStats,
+}
+
+impl ReportCheckpoint {
+    /// A checkpoint that has sent nothing yet.
+    pub fn new() -> Self {
+        Self {
+            last_record_timestamp: "0".into(),
+            report_files: Default::default(),
+            parse_errors: Default::default(),
+        }
+    }
+}
+
+/// Number of malformed line samples kept in [ParseErrorStats].
+const MAX_PARSE_ERROR_SAMPLES: usize = 10;
+
+/// Length at which malformed line samples are truncated.
+const MAX_PARSE_ERROR_SAMPLE_CHARS: usize = 256;
+
+/// Counts and samples of malformed report lines.
+#[derive(
+    Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseErrorStats {
+    /// Total malformed lines seen.
+    pub total: u64,
+
+    /// Malformed lines seen, by reason.
+    pub by_reason: std::collections::BTreeMap<String, u64>,
+
+    /// The most recent malformed lines, truncated.
+    pub samples: Vec<MalformedLine>,
+
+    /// How many of the total have been reported to the collector.
+    pub reported: u64,
+}
+
+impl ParseErrorStats {
+    fn record(&mut self, malformed: &MalformedLine) {
+        self.total += 1;
+        *self.by_reason.entry(malformed.reason.clone()).or_default() += 1;
+
+        let mut sample = malformed.clone();
+        if let Some((idx, _)) = sample
+            .content
+            .char_indices()
+            .nth(MAX_PARSE_ERROR_SAMPLE_CHARS)
+        {
+            sample.content.truncate(idx);
+        }
+
+        if self.samples.len() >= MAX_PARSE_ERROR_SAMPLES {
+            self.samples.remove(0);
+        }
+        self.samples.push(sample);
+    }
+
+    /// Build a "parseErrors" summary proof of the malformed lines that
+    /// have not yet been reported, if any.
+    pub fn summary_proof(&self) -> Result<Option<String>> {
+        let unreported = self.total.saturating_sub(self.reported);
+
+        if unreported == 0 {
+            return Ok(None);
+        }
+
+        #[derive(serde::Serialize)]
+        struct Summary<'a> {
+            k: &'static str,
+            t: String,
+            n: String,
+            c: String,
+            r: &'a std::collections::BTreeMap<String, u64>,
+        }
+
+        let t = unix_time()?.as_micros().to_string();
+
+        Ok(Some(serde_json::to_string(&Summary {
+            k: "parseErrors",
+            t,
+            n: unreported.to_string(),
+            c: self.total.to_string(),
+            r: &self.by_reason,
+        })?))
+    }
+}
+
+/// A report line that could not be processed.
+#[derive(
+    Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct MalformedLine {
+    /// The report file.
+    pub path: std::path::PathBuf,
+
+    /// One-based line number within the file.
+    pub line: u64,
+
+    /// Why the line was rejected.
+    pub reason: String,
+
+    /// The line content (empty if it was too long to keep).
+    pub content: String,
+}
+
+/// Per-file read state.
+#[derive(
+    Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct FileCheckpoint {
+    /// File length when it was last read.
+    pub len: u64,
+
+    /// File modification time in milliseconds when it was last read.
+    pub modified: u64,
+
+    /// Byte offset up to which an uncompressed file has been processed.
+    /// Always the start of a line.
+    #[serde(default)]
+    pub offset: u64,
+
+    /// Number of lines before `offset`.
+    #[serde(default)]
+    pub line: u64,
+
+    /// Identity of the file content, which survives a rename: the device
+    /// and inode on unix, otherwise a hash of the first block.
+    #[serde(default)]
+    pub id: String,
+}
+
+impl FileCheckpoint {
+    async fn read(path: &std::path::Path) -> Result<Self> {
+        let meta = tokio::fs::metadata(path).await?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Ok(Self {
+            len: meta.len(),
+            modified,
+            offset: 0,
+            line: 0,
+            id: file_id(path, &meta).await?,
+        })
+    }
+}
+
+#[cfg(unix)]
+async fn file_id(
+    _path: &std::path::Path,
+    meta: &std::fs::Metadata,
+) -> Result<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(format!("{}:{}", meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+async fn file_id(
+    path: &std::path::Path,
+    _meta: &std::fs::Metadata,
+) -> Result<String> {
+    use base64::prelude::*;
+    use blake2::Digest;
+    use tokio::io::AsyncReadExt;
+
+    let mut block = Vec::with_capacity(4096);
+    tokio::fs::File::open(path)
+        .await?
+        .take(4096)
+        .read_to_end(&mut block)
+        .await?;
+
+    let hash = blake2::Blake2b::<blake2::digest::consts::U16>::digest(&block);
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(hash))
+}
+
+/// Compression of a report file, determined by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Plain text.
+    None,
+
+    /// Gzip, `*.gz`.
+    Gzip,
+
+    /// Zstandard, `*.zst`.
+    Zstd,
+}
+
+impl Compression {
+    /// Determine the compression of a report file from its name.
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") | Some("zstd") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+}
+
+/// Open a report file at a byte offset, transparently decompressing it if
+/// needed. Compressed files can only be opened at offset zero.
+pub async fn open_report(
+    path: &std::path::Path,
+    offset: u64,
+) -> Result<std::pin::Pin<Box<dyn tokio::io::AsyncBufRead + Send>>> {
+    use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+    use tokio::io::{AsyncSeekExt, BufReader};
+
+    let compression = Compression::from_path(path);
+
+    if offset > 0 && compression != Compression::None {
+        return Err(std::io::Error::other(
+            "cannot seek within a compressed report file",
+        ));
+    }
+
+    let mut f = tokio::fs::File::open(path).await?;
+    if offset > 0 {
+        f.seek(std::io::SeekFrom::Start(offset)).await?;
+    }
+    let f = BufReader::new(f);
+
+    Ok(match compression {
+        Compression::None => Box::pin(f),
+        Compression::Gzip => {
+            let mut d = GzipDecoder::new(f);
+            d.multiple_members(true);
+            Box::pin(BufReader::new(d))
+        }
+        Compression::Zstd => {
+            let mut d = ZstdDecoder::new(f);
+            d.multiple_members(true);
+            Box::pin(BufReader::new(d))
+        }
+    })
+}
+
+/// Default maximum length of a single report line.
+pub const DEFAULT_MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// Default maximum number of proofs per metrics request.
+pub const DEFAULT_BATCH_MAX_PROOFS: usize = 100;
+
+/// Default maximum size of a metrics request body in bytes.
+pub const DEFAULT_BATCH_MAX_BYTES: usize = 1024 * 1024;
+
+/// Default number of batches that may be awaiting acknowledgement at once.
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+
+/// The report kind sent by default.
+pub const DEFAULT_REPORT_KIND: &str = "fetchedOps";
+
+/// Options controlling which report files are read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadOptions {
+    /// Glob patterns of files to skip.
+    pub exclude: Vec<String>,
+
+    /// Maximum directory depth below the literal prefix of a glob pattern.
+    /// `None` means unlimited.
+    pub max_depth: Option<usize>,
+
+    /// Lines longer than this are discarded.
+    pub max_line_bytes: usize,
+
+    /// If set, malformed lines are appended to this file.
+    pub dead_letter_path: Option<std::path::PathBuf>,
+
+    /// Maximum number of proofs passed to the callback at once.
+    pub batch_max_proofs: usize,
+
+    /// Maximum size of the metrics request for a batch, as computed by
+    /// `sizer`. A single proof larger than this is still sent on its own.
+    pub batch_max_bytes: usize,
+
+    /// Computes metrics request sizes.
+    pub sizer: MetricsSizer,
+
+    /// Maximum number of callback futures in flight at once. Batches are
+    /// still acknowledged in order.
+    pub upload_concurrency: usize,
+
+    /// Report kinds (the `k` field) to send. Lines of other kinds are
+    /// skipped.
+    pub kinds: Vec<String>,
+
+    /// If set, proofs with a later timestamp are skipped.
+    pub until: Option<u64>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            exclude: Vec::new(),
+            max_depth: None,
+            max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+            dead_letter_path: None,
+            batch_max_proofs: DEFAULT_BATCH_MAX_PROOFS,
+            batch_max_bytes: DEFAULT_BATCH_MAX_BYTES,
+            sizer: MetricsSizer::default(),
+            upload_concurrency: DEFAULT_UPLOAD_CONCURRENCY,
+            kinds: vec![DEFAULT_REPORT_KIND.into()],
+            until: None,
+        }
+    }
+}
+
+impl ReadOptions {
+    /// Construct read options from a runtime config.
+    pub fn from_config(config: &RuntimeConfigFile) -> Result<Self> {
+        Ok(Self {
+            exclude: config.report_exclude_list.clone(),
+            max_depth: config.report_max_depth,
+            max_line_bytes: config.report_max_line_bytes,
+            dead_letter_path: Some(config.dead_letter_path()),
+            batch_max_proofs: config.batch_max_proofs,
+            batch_max_bytes: config.batch_max_bytes,
+            sizer: MetricsSizer::new(config)?,
+            upload_concurrency: config.upload_concurrency,
+            ..Default::default()
+        })
+    }
+}
+
+/// List the report files matching the path list. Entries that name an
+/// existing directory are scanned (non-recursively) for `*.jsonl` files
+/// and their compressed `*.jsonl.gz` / `*.jsonl.zst` rotations, any other
+/// entry is treated as a glob pattern. A plain path that doesn't exist is
+/// an error.
+pub async fn list_report_files(
     path_list: &[std::path::PathBuf],
-    ignore_before: String,
-    mut cb: C,
-) -> Result<String>
-where
-    F: std::future::Future<Output = Result<()>>,
-    C: FnMut(Vec<String>) -> F,
-{
-    let ignore_before: u64 =
-        ignore_before.parse().map_err(std::io::Error::other)?;
-    let mut max_ignore_before = ignore_before;
+    opts: &ReadOptions,
+) -> Result<Vec<std::path::PathBuf>> {
+    let path_list = path_list.to_vec();
+    let opts = opts.clone();
+    tokio::task::spawn_blocking(move || {
+        list_report_files_sync(&path_list, &opts)
+    })
+    .await?
+}
 
-    use tokio::io::AsyncBufReadExt;
+fn list_report_files_sync(
+    path_list: &[std::path::PathBuf],
+    opts: &ReadOptions,
+) -> Result<Vec<std::path::PathBuf>> {
+    let exclude = build_glob_set(&opts.exclude)?;
 
-    let mut proofs = Vec::new();
+    let mut out = Vec::new();
 
-    for dir in path_list.iter() {
-        let mut dir = tokio::fs::read_dir(dir).await?;
-        while let Ok(Some(e)) = dir.next_entry().await {
-            let f = e.file_name().to_string_lossy().to_string();
-            if !f.ends_with(".jsonl") {
+    for entry in path_list.iter() {
+        // a plain path that doesn't exist is most likely a typo, not a
+        // pattern, so don't let it silently match nothing
+        if !is_glob(entry) && !entry.exists() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{}: report path does not exist", entry.display()),
+            ));
+        }
+
+        let (base, matcher, max_depth) = if entry.is_dir() {
+            (Some(entry.clone()), None, Some(1))
+        } else {
+            let pattern = entry.to_string_lossy();
+            let matcher = build_glob_set(&[pattern.to_string()])?;
+            (glob_base(entry), Some(matcher), opts.max_depth)
+        };
+
+        // relative patterns without a literal prefix are walked from the
+        // working directory, but matched without the leading "./"
+        let implicit_base = base.is_none();
+        let base = base.unwrap_or_else(|| ".".into());
+
+        let mut walk = walkdir::WalkDir::new(&base).follow_links(true);
+        if let Some(max_depth) = max_depth {
+            walk = walk.max_depth(max_depth);
+        }
+
+        for e in walk {
+            let e = match e {
+                Ok(e) => e,
+                // a pattern whose literal prefix doesn't exist (yet)
+                // simply matches nothing
+                Err(err) if matcher.is_some() && err.depth() == 0 => break,
+                // following links under "**" can come back round to an
+                // ancestor, skip that branch rather than fail the read
+                Err(err) if err.loop_ancestor().is_some() => {
+                    tracing::warn!("skipping symlink loop: {err}");
+                    continue;
+                }
+                Err(err) => return Err(std::io::Error::other(err)),
+            };
+
+            if !e.file_type().is_file() {
                 continue;
             }
-            let f = tokio::fs::File::open(e.path()).await?;
-            let f = tokio::io::BufReader::new(f);
-            let mut f = f.lines();
-
-            while let Some(line) = f.next_line().await? {
-                let p: Parse = match serde_json::from_str(&line) {
-                    Ok(p) => p,
-                    Err(_) => continue,
-                };
 
-                if p.k != "fetchedOps" {
+            let path = match implicit_base {
+                true => e.path().strip_prefix(".").unwrap_or(e.path()),
+                false => e.path(),
+            };
+
+            match &matcher {
+                Some(matcher) => {
+                    if !matcher.is_match(path) {
+                        continue;
+                    }
+                }
+                None => {
+                    let name = e.file_name().to_string_lossy();
+                    if !REPORT_SUFFIXES.iter().any(|s| name.ends_with(s)) {
+                        continue;
+                    }
+                }
+            }
+
+            if exclude.is_match(path) {
+                continue;
+            }
+
+            out.push(path.to_path_buf());
+        }
+    }
+
+    out.sort();
+    out.dedup();
+
+    Ok(out)
+}
+
+const REPORT_SUFFIXES: &[&str] = &[".jsonl", ".jsonl.gz", ".jsonl.zst"];
+
+fn is_glob(path: &std::path::Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '[', '{'])
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<globset::GlobSet> {
+    let mut set = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        set.add(
+            globset::GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(std::io::Error::other)?,
+        );
+    }
+    set.build().map_err(std::io::Error::other)
+}
+
+/// The leading components of a glob pattern that contain no wildcards.
+fn glob_base(pattern: &std::path::Path) -> Option<std::path::PathBuf> {
+    let mut base = std::path::PathBuf::new();
+    for c in pattern.components() {
+        let s = c.as_os_str().to_string_lossy();
+        if s.contains(['*', '?', '[', '{']) {
+            break;
+        }
+        base.push(c);
+    }
+    if base.as_os_str().is_empty() {
+        return None;
+    }
+    Some(base)
+}
+
+/// A single raw line read from a report file.
+enum RawLine {
+    /// A complete line, without its terminating newline.
+    Line(Vec<u8>),
+
+    /// A complete line that exceeded the maximum length and was discarded.
+    TooLong,
+
+    /// The end of the file.
+    Eof,
+}
+
+/// A report file being read as one input of the merge.
+struct Source {
+    path: std::path::PathBuf,
+    compressed: Option<FileCheckpoint>,
+    meta: FileCheckpoint,
+    reader: std::pin::Pin<Box<dyn tokio::io::AsyncBufRead + Send>>,
+    max_line_bytes: usize,
+
+    /// Offset just past the last complete line read.
+    offset: u64,
+
+    /// Number of complete lines read.
+    line: u64,
+
+    /// Offset of the start of the current head proof.
+    head_offset: u64,
+
+    /// Number of lines before the current head proof.
+    head_line: u64,
+}
+
+impl Source {
+    /// Read the next newline-terminated line. An unterminated final line
+    /// of an uncompressed file is treated as still being written and left
+    /// unread. Compressed files are complete, so their final line is
+    /// accepted as-is.
+    async fn next_line(&mut self) -> Result<RawLine> {
+        use tokio::io::AsyncBufReadExt;
+
+        let mut line = Vec::new();
+        let mut len = 0;
+
+        loop {
+            let buf = self.reader.fill_buf().await?;
+
+            if buf.is_empty() {
+                if len == 0 || self.compressed.is_none() {
+                    return Ok(RawLine::Eof);
+                }
+                self.offset += len as u64;
+                self.line += 1;
+                return Ok(match len > self.max_line_bytes {
+                    true => RawLine::TooLong,
+                    false => RawLine::Line(line),
+                });
+            }
+
+            let (chunk, done) = match buf.iter().position(|b| *b == b'\n') {
+                Some(pos) => (&buf[..pos], Some(pos + 1)),
+                None => (buf, None),
+            };
+
+            len += chunk.len();
+            if len <= self.max_line_bytes {
+                line.extend_from_slice(chunk);
+            } else {
+                // keep consuming the line, but stop buffering it
+                line = Vec::new();
+            }
+
+            match done {
+                Some(consume) => {
+                    self.reader.as_mut().consume(consume);
+                    self.offset += len as u64 + 1;
+                    self.line += 1;
+                    return Ok(match len > self.max_line_bytes {
+                        true => RawLine::TooLong,
+                        false => RawLine::Line(line),
+                    });
+                }
+                None => {
+                    let consume = chunk.len();
+                    self.reader.as_mut().consume(consume);
+                }
+            }
+        }
+    }
+
+    /// Read the next proof of a wanted kind with a timestamp after
+    /// `ignore_before`, collecting any malformed lines along the way into
+    /// `pending`.
+    async fn next_proof(
+        &mut self,
+        ignore_before: u64,
+        opts: &ReadOptions,
+        pending: &mut Vec<MalformedLine>,
+    ) -> Result<Option<(u64, String)>> {
+        loop {
+            let start = self.offset;
+            let start_line = self.line;
+
+            let path = self.path.clone();
+            let malformed = |reason: &str, content: String| MalformedLine {
+                path: path.clone(),
+                line: start_line + 1,
+                reason: reason.into(),
+                content,
+            };
+
+            let line = match self.next_line().await? {
+                RawLine::Eof => return Ok(None),
+                RawLine::TooLong => {
+                    pending.push(malformed("tooLong", String::new()));
                     continue;
                 }
+                RawLine::Line(mut line) => {
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    line
+                }
+            };
 
-                let t: u64 = match p.t.parse() {
-                    Ok(t) => t,
-                    Err(_) => continue,
-                };
+            let line = match String::from_utf8(line) {
+                Ok(line) => line,
+                Err(err) => {
+                    let content =
+                        String::from_utf8_lossy(err.as_bytes()).to_string();
+                    pending.push(malformed("invalidUtf8", content));
+                    continue;
+                }
+            };
+
+            let p: Parse = match serde_json::from_str(&line) {
+                Ok(p) => p,
+                Err(_) => {
+                    pending.push(malformed("invalidJson", line));
+                    continue;
+                }
+            };
+
+            if !opts.kinds.contains(&p.k) {
+                continue;
+            }
 
-                if t <= ignore_before {
+            let t: u64 = match p.t.parse() {
+                Ok(t) => t,
+                Err(_) => {
+                    pending.push(malformed("invalidTimestamp", line));
                     continue;
                 }
+            };
+
+            if t <= ignore_before || opts.until.is_some_and(|until| t > until) {
+                continue;
+            }
+
+            self.head_offset = start;
+            self.head_line = start_line;
+
+            return Ok(Some((t, line)));
+        }
+    }
+}
+
+/// Where malformed lines are recorded, once the batch they were read with
+/// has been accepted.
+struct Quarantine {
+    dead_letter_path: Option<std::path::PathBuf>,
+    dead_letter: Option<tokio::fs::File>,
+    count: u64,
+}
+
+impl Quarantine {
+    fn new(dead_letter_path: Option<std::path::PathBuf>) -> Self {
+        Self {
+            dead_letter_path,
+            dead_letter: None,
+            count: 0,
+        }
+    }
+
+    async fn record(
+        &mut self,
+        stats: &mut ParseErrorStats,
+        malformed: MalformedLine,
+    ) {
+        tracing::debug!(
+            path = ?malformed.path,
+            line = malformed.line,
+            reason = malformed.reason,
+            "malformed report line",
+        );
+
+        self.count += 1;
+        stats.record(&malformed);
+
+        if let Err(err) = self.write_dead_letter(&malformed).await {
+            tracing::warn!(?err, "failed to write dead-letter file");
+        }
+    }
+
+    async fn write_dead_letter(
+        &mut self,
+        malformed: &MalformedLine,
+    ) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let path = match &self.dead_letter_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if self.dead_letter.is_none() {
+            self.dead_letter = Some(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            );
+        }
+
+        let mut line = serde_json::to_string(malformed)?;
+        line.push('\n');
+
+        if let Some(f) = &mut self.dead_letter {
+            f.write_all(line.as_bytes()).await?;
+            f.flush().await?;
+        }
 
-                if t > max_ignore_before {
-                    max_ignore_before = t;
+        Ok(())
+    }
+}
+
+/// Read reports from disk, updating the checkpoint as each batch of proofs
+/// is accepted by the callback.
+///
+/// Lines from all matching files are merged in timestamp order (assuming
+/// each file is itself in order), so every batch is monotonic and the
+/// checkpoint never moves past a proof that has not been sent.
+///
+/// Reading continues while earlier batches are in flight, with up to
+/// `upload_concurrency` callback futures pending at once. If the callback
+/// fails, the checkpoint reflects the leading batches accepted so far, and
+/// any later batches still in flight are dropped.
+///
+/// Uncompressed files are resumed from the offset of the last processed
+/// line, and a final line without a trailing newline is left for the next
+/// run. Compressed files are treated as complete, rotated files. Once read
+/// they are skipped for as long as their length and modification time
+/// don't change, including after being renamed by a further rotation.
+pub async fn read_reports<F, C>(
+    path_list: &[std::path::PathBuf],
+    checkpoint: &mut ReportCheckpoint,
+    opts: &ReadOptions,
+    cb: C,
+) -> Result<()>
+where
+    F: std::future::Future<Output = Result<()>>,
+    C: FnMut(Vec<String>) -> F,
+{
+    let ignore_before: u64 = checkpoint
+        .last_record_timestamp
+        .parse()
+        .map_err(std::io::Error::other)?;
+
+    let files = list_report_files(path_list, opts).await?;
+
+    let mut sources = Vec::new();
+    let mut renamed = Vec::new();
+
+    for path in files.iter().cloned() {
+        let meta = FileCheckpoint::read(&path).await?;
+        let prev = checkpoint.report_files.get(&path);
+
+        let is_compressed = Compression::from_path(&path) != Compression::None;
+
+        let (compressed, offset, line) = if is_compressed {
+            // match on content identity, a rotation may have renamed it
+            if checkpoint.report_files.values().any(|prev| *prev == meta) {
+                tracing::trace!(?path, "skipping already read file");
+                if prev != Some(&meta) {
+                    renamed.push((path, meta));
+                }
+                continue;
+            }
+            (Some(meta.clone()), 0, 0)
+        } else {
+            match prev {
+                // a different or shorter file has replaced the one we read
+                Some(prev) if prev.id == meta.id && prev.offset <= meta.len => {
+                    (None, prev.offset, prev.line)
                 }
+                _ => (None, 0, 0),
+            }
+        };
+
+        let reader = open_report(&path, offset).await?;
+
+        sources.push(Source {
+            path,
+            compressed,
+            meta,
+            reader,
+            max_line_bytes: opts.max_line_bytes,
+            offset,
+            line,
+            head_offset: offset,
+            head_line: line,
+        });
+    }
 
-                proofs.push(line);
+    // forget about files that have been rotated away
+    checkpoint.report_files.retain(|p, _| files.contains(p));
+    checkpoint.report_files.extend(renamed);
 
-                if proofs.len() >= 100 {
-                    cb(std::mem::take(&mut proofs)).await?;
+    let mut quarantine = Quarantine::new(opts.dead_letter_path.clone());
+
+    // merging runs at most one batch ahead of the uploads
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+
+    let (merged, uploaded) = tokio::join!(
+        merge_sources(&mut sources, opts, ignore_before, tx),
+        upload_batches(checkpoint, &mut quarantine, opts, rx, cb),
+    );
+
+    if quarantine.count > 0 {
+        tracing::warn!(
+            "Quarantined {} malformed report lines ({} total){}",
+            quarantine.count,
+            checkpoint.parse_errors.total,
+            match &opts.dead_letter_path {
+                Some(path) => format!(", see {}", path.display()),
+                None => String::new(),
+            },
+        );
+    }
+
+    // a failed upload also stops the merge, so it takes precedence
+    uploaded.and(merged)
+}
+
+/// A batch of proofs, along with the checkpoint to record once it has
+/// been accepted.
+struct Batch {
+    proofs: Vec<String>,
+    last_t: u64,
+    files: Vec<(std::path::PathBuf, FileCheckpoint)>,
+    malformed: Vec<MalformedLine>,
+}
+
+async fn merge_sources(
+    sources: &mut [Source],
+    opts: &ReadOptions,
+    ignore_before: u64,
+    tx: tokio::sync::mpsc::Sender<Batch>,
+) -> Result<()> {
+    use std::cmp::Reverse;
+
+    // the next proof from each source, keyed by timestamp
+    let mut heads = Vec::with_capacity(sources.len());
+    let mut heap = std::collections::BinaryHeap::new();
+    let mut malformed = Vec::new();
+
+    for (idx, source) in sources.iter_mut().enumerate() {
+        let head = source
+            .next_proof(ignore_before, opts, &mut malformed)
+            .await?;
+        if let Some((t, _)) = &head {
+            heap.push(Reverse((*t, idx)));
+        }
+        heads.push(head);
+    }
+
+    let mut proofs = Vec::new();
+    let mut batch_bytes = opts.sizer.base();
+    let mut last_t = ignore_before;
+
+    while let Some(Reverse((t, idx))) = heap.peek().copied() {
+        let (_, line) = heads[idx].as_ref().expect("heap entry has a head");
+        let size = opts.sizer.proof_size(line);
+
+        // flush before a proof that would overflow the batch, but never
+        // split proofs sharing a timestamp across batches, or the
+        // checkpoint could skip the second half
+        let full = proofs.len() >= opts.batch_max_proofs.max(1)
+            || batch_bytes + size > opts.batch_max_bytes;
+        if !proofs.is_empty() && full && t != last_t {
+            let batch = cut_batch(
+                std::mem::take(&mut proofs),
+                std::mem::take(&mut malformed),
+                sources,
+                &heads,
+                last_t,
+            );
+            if tx.send(batch).await.is_err() {
+                // the upload failed, and will report its own error
+                return Ok(());
+            }
+            batch_bytes = opts.sizer.base();
+        }
+
+        heap.pop();
+        let (_, line) = heads[idx].take().expect("heap entry has a head");
+        if opts.sizer.base() + size > opts.batch_max_bytes {
+            tracing::warn!(
+                "proof of {size} bytes exceeds the batch byte limit of {}",
+                opts.batch_max_bytes,
+            );
+        }
+        proofs.push(line);
+        batch_bytes += size;
+        last_t = t;
+
+        let head = sources[idx]
+            .next_proof(ignore_before, opts, &mut malformed)
+            .await?;
+        if let Some((t, _)) = &head {
+            heap.push(Reverse((*t, idx)));
+        }
+        heads[idx] = head;
+    }
+
+    // always send the final batch, even if empty, so that skipped lines
+    // and fully read files are recorded
+    let _ = tx
+        .send(cut_batch(proofs, malformed, sources, &heads, last_t))
+        .await;
+
+    Ok(())
+}
+
+/// Capture progress at a batch boundary. Everything before each source's
+/// pending head proof has now been either batched, skipped or found to be
+/// malformed.
+fn cut_batch(
+    proofs: Vec<String>,
+    malformed: Vec<MalformedLine>,
+    sources: &[Source],
+    heads: &[Option<(u64, String)>],
+    last_t: u64,
+) -> Batch {
+    let mut files = Vec::with_capacity(sources.len());
+
+    for (source, head) in sources.iter().zip(heads.iter()) {
+        match &source.compressed {
+            Some(meta) => {
+                if head.is_none() {
+                    files.push((source.path.clone(), meta.clone()));
                 }
             }
+            None => {
+                let (offset, line) = match head {
+                    Some(_) => (source.head_offset, source.head_line),
+                    None => (source.offset, source.line),
+                };
+                files.push((
+                    source.path.clone(),
+                    FileCheckpoint {
+                        offset,
+                        line,
+                        ..source.meta.clone()
+                    },
+                ));
+            }
         }
     }
 
-    if !proofs.is_empty() {
-        cb(proofs).await?;
+    Batch {
+        proofs,
+        last_t,
+        files,
+        malformed,
+    }
+}
+
+/// Pass batches to the callback with up to `upload_concurrency` of them
+/// in flight. Results are taken in order, so the checkpoint only ever
+/// advances past a contiguous run of accepted batches. Malformed lines
+/// are quarantined along with the checkpoint of their batch, so a retry
+/// doesn't count them twice.
+async fn upload_batches<F, C>(
+    checkpoint: &mut ReportCheckpoint,
+    quarantine: &mut Quarantine,
+    opts: &ReadOptions,
+    rx: tokio::sync::mpsc::Receiver<Batch>,
+    mut cb: C,
+) -> Result<()>
+where
+    F: std::future::Future<Output = Result<()>>,
+    C: FnMut(Vec<String>) -> F,
+{
+    use futures_util::StreamExt;
+
+    let batches = futures_util::stream::unfold(rx, |mut rx| async {
+        rx.recv().await.map(|batch| (batch, rx))
+    });
+
+    let accepted = batches
+        .map(|mut batch| {
+            let proofs = std::mem::take(&mut batch.proofs);
+            let sent = (!proofs.is_empty()).then(|| cb(proofs));
+            async move {
+                if let Some(sent) = sent {
+                    sent.await?;
+                }
+                Result::Ok(batch)
+            }
+        })
+        .buffered(opts.upload_concurrency.max(1));
+    let mut accepted = std::pin::pin!(accepted);
+
+    while let Some(batch) = accepted.next().await {
+        let batch = batch?;
+        checkpoint.last_record_timestamp = batch.last_t.to_string();
+        checkpoint.report_files.extend(batch.files);
+        for malformed in batch.malformed {
+            quarantine
+                .record(&mut checkpoint.parse_errors, malformed)
+                .await;
+        }
     }
 
-    Ok(max_ignore_before.to_string())
+    Ok(())
 }
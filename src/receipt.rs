@@ -0,0 +1,113 @@
+//! Signed delivery receipts from the collector.
+
+use crate::*;
+
+/// A collector's signed acknowledgement that it stored a metrics batch.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Receipt {
+    /// The id of the accepted batch, see [SignedMetrics::batch_id].
+    pub batch_id: String,
+
+    /// When the collector accepted the batch, in unix milliseconds.
+    pub accepted_at: u64,
+
+    /// Collector signature over the batch id and acceptance time.
+    pub signature: String,
+}
+
+impl Receipt {
+    /// Check the receipt is for `batch_id` and signed by `collector_pub_key`.
+    pub fn verify(
+        &self,
+        collector_pub_key: &PubKey,
+        batch_id: &str,
+    ) -> Result<()> {
+        if self.batch_id != batch_id {
+            return Err(std::io::Error::other(format!(
+                "receipt is for batch {}, expected {batch_id}",
+                self.batch_id,
+            )));
+        }
+
+        collector_pub_key
+            .verify(self.signed_content()?.as_bytes(), &self.signature)
+            .map_err(|err| {
+                std::io::Error::other(format!(
+                    "receipt for batch {batch_id}: {err}"
+                ))
+            })
+    }
+
+    /// The content the collector signs.
+    pub fn signed_content(&self) -> Result<String> {
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Sig<'a> {
+            accepted_at: u64,
+            batch_id: &'a str,
+        }
+
+        Ok(serde_json::to_string(&Sig {
+            accepted_at: self.accepted_at,
+            batch_id: &self.batch_id,
+        })?)
+    }
+}
+
+/// A receipt as stored locally, with the endpoint that issued it.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredReceipt {
+    /// The collector endpoint that accepted the batch.
+    pub endpoint: String,
+
+    /// The receipt.
+    #[serde(flatten)]
+    pub receipt: Receipt,
+}
+
+/// Append receipts to a jsonl file, one per line.
+pub async fn store_receipts(
+    path: &std::path::Path,
+    receipts: &[StoredReceipt],
+) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    if receipts.is_empty() {
+        return Ok(());
+    }
+
+    let mut data = String::new();
+    for receipt in receipts {
+        data.push_str(&serde_json::to_string(receipt)?);
+        data.push('\n');
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    // a single write, so concurrent uploads don't interleave lines
+    file.write_all(data.as_bytes()).await?;
+    file.sync_data().await
+}
+
+/// Read all stored receipts.
+pub async fn load_receipts(
+    path: &std::path::Path,
+) -> Result<Vec<StoredReceipt>> {
+    let data = match tokio::fs::read_to_string(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(err),
+    };
+
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).map_err(std::io::Error::other))
+        .collect()
+}
@@ -0,0 +1,135 @@
+//! Re-sending a time range of report proofs, e.g. after collector data
+//! loss, without affecting the regular checkpoint.
+
+use crate::*;
+
+/// Progress of a replay, persisted so an interrupted replay can resume.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayState {
+    /// First timestamp of the range, inclusive.
+    pub from: u64,
+
+    /// Last timestamp of the range, inclusive.
+    pub to: u64,
+
+    /// Report kinds being replayed.
+    pub kinds: Vec<String>,
+
+    /// Timestamp of the last proof sent. Proofs up to here are done.
+    pub last_record_timestamp: u64,
+
+    /// Number of proofs sent so far.
+    pub proofs: u64,
+
+    /// Random id mixed into the batch ids of this replay, so the collector
+    /// doesn't skip batches it remembers from the original delivery. It is
+    /// kept on resume, so a batch accepted just before an interruption is
+    /// still recognized as already received.
+    pub replay_id: String,
+}
+
+fn new_replay_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+impl ReplayState {
+    /// A replay that has sent nothing yet.
+    pub fn new(from: u64, to: u64, kinds: Vec<String>) -> Self {
+        Self {
+            from,
+            to,
+            kinds,
+            last_record_timestamp: from.saturating_sub(1),
+            proofs: 0,
+            replay_id: new_replay_id(),
+        }
+    }
+
+    /// Load a saved replay of the same range and kinds, if there is one.
+    /// A saved replay without a replay id is an error, resuming it under a
+    /// new id would re-send its last batch as new.
+    pub async fn resume(
+        path: &std::path::Path,
+        from: u64,
+        to: u64,
+        kinds: &[String],
+    ) -> Result<Option<Self>> {
+        let data = match tokio::fs::read_to_string(path).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+
+        let state: Self = serde_json::from_str(&data).map_err(|err| {
+            std::io::Error::other(format!(
+                "{}: invalid saved replay, remove it to start over: {err}",
+                path.display(),
+            ))
+        })?;
+
+        if state.from != from || state.to != to || state.kinds != kinds {
+            tracing::warn!(
+                "ignoring saved replay of {}..={} {:?}",
+                state.from,
+                state.to,
+                state.kinds,
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(state))
+    }
+
+    /// Save the state, replacing the file atomically.
+    pub async fn save(&self, path: &std::path::Path) -> Result<()> {
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, serde_json::to_string(self)?).await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+
+    /// Record a sent batch of proofs.
+    pub fn record(&mut self, proofs: &[String]) -> Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Parse {
+            t: String,
+        }
+
+        if let Some(last) = proofs.last() {
+            let p: Parse =
+                serde_json::from_str(last).map_err(std::io::Error::other)?;
+            self.last_record_timestamp =
+                p.t.parse().map_err(std::io::Error::other)?;
+        }
+        self.proofs += proofs.len() as u64;
+
+        Ok(())
+    }
+
+    /// Rough progress through the range, in percent.
+    pub fn percent(&self) -> f64 {
+        let span = self.to.saturating_sub(self.from).max(1);
+        let done = self
+            .last_record_timestamp
+            .saturating_sub(self.from.saturating_sub(1));
+        (done as f64 * 100.0 / span as f64).min(100.0)
+    }
+}
+
+/// The outcome of a replay.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayReport {
+    /// Whether an interrupted replay was resumed.
+    pub resumed: bool,
+
+    /// The range end actually used, after limiting it to what the service
+    /// has already sent.
+    pub to: u64,
+
+    /// Number of proofs sent, including before a resume.
+    pub proofs: u64,
+}
@@ -37,4 +37,2427 @@ async fn config_init() {
     println!("{data:#?}");
 
     assert_eq!(&c, &data);
+
+    // malformed lines go next to the config file unless configured
+    assert_eq!(
+        dir.path().join("runtime-config.json.dead-letter.jsonl"),
+        c.dead_letter_path_for(&path),
+    );
+}
+
+/// Write a conductor config with its data root at each of `roots`, and
+/// return a runtime config that reports on them.
+async fn conductor_fixture(roots: &[&std::path::Path]) -> RuntimeConfig {
+    let mut conductors = Vec::new();
+    for root in roots {
+        let dht = root.join("databases").join("dht");
+        tokio::fs::create_dir_all(&dht).await.unwrap();
+
+        let conductor = root.join("conductor-config.yaml");
+        tokio::fs::write(
+            &conductor,
+            format!("data_root_path: {}\n", root.display()),
+        )
+        .await
+        .unwrap();
+        conductors.push(conductor);
+    }
+
+    RuntimeConfig::with_init(
+        "http://127.0.0.1:8787".into(),
+        "pub".into(),
+        "sec".into(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        conductors,
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn db_size_suppresses_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut c = conductor_fixture(&[dir.path()]).await;
+    let dht = dir.path().join("databases").join("dht");
+    tokio::fs::write(dht.join("space-a"), vec![0; 1000])
+        .await
+        .unwrap();
+
+    c.db_size_threshold_percent = 10.0;
+
+    // first check always reports
+    let (proofs, state) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert_eq!(1, proofs.len());
+    c.db_size_state = state;
+
+    // a small change is suppressed
+    tokio::fs::write(dht.join("space-a"), vec![0; 1050])
+        .await
+        .unwrap();
+    let (proofs, state) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert!(proofs.is_empty());
+    let reported = &state.last_reported[&c.conductor_config_path_list[0]];
+    assert_eq!(Some(&1000), reported.get("space-a"));
+    c.db_size_state = state;
+
+    // a large change is reported
+    tokio::fs::write(dht.join("space-a"), vec![0; 2000])
+        .await
+        .unwrap();
+    let (proofs, state) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert_eq!(1, proofs.len());
+    c.db_size_state = state;
+
+    // the heartbeat forces a full snapshot
+    c.db_size_state.last_snapshot = 0;
+    let (proofs, _) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert_eq!(1, proofs.len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn db_size_conductors_sharing_a_space() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let (one, two) = (dir.path().join("one"), dir.path().join("two"));
+    let mut c = conductor_fixture(&[&one, &two]).await;
+    let conductors = c.conductor_config_path_list.clone();
+    for (root, len) in [(one, 1000), (two, 2000)] {
+        let dht = root.join("databases").join("dht");
+        tokio::fs::write(dht.join("space-a"), vec![0; len])
+            .await
+            .unwrap();
+    }
+
+    // each conductor is reported, and tracked, separately
+    let (proofs, state) = crate::db_size::check_db_size(&c).await.unwrap();
+    let mut sizes: Vec<String> = proofs
+        .iter()
+        .map(|p| serde_json::from_str::<serde_json::Value>(p).unwrap())
+        .map(|p| p["b"].as_str().unwrap().to_string())
+        .collect();
+    sizes.sort();
+    assert_eq!(vec!["1000", "2000"], sizes);
+    assert_eq!(
+        Some(&1000),
+        state.last_reported[&conductors[0]].get("space-a")
+    );
+    assert_eq!(
+        Some(&2000),
+        state.last_reported[&conductors[1]].get("space-a")
+    );
+    c.db_size_state = state;
+
+    let (proofs, _) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert!(proofs.is_empty());
+
+    // a huge heartbeat saturates rather than overflowing
+    c.db_size_heartbeat_hours = u64::MAX;
+    let (proofs, _) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert!(proofs.is_empty());
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn disk_usage_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    let stats = crate::disk_usage::fs_stats(dir.path().into())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stats.total_bytes > 0);
+    assert!(stats.available_bytes <= stats.total_bytes);
+    assert!(stats.used_bytes <= stats.total_bytes);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn db_size_accurate_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut c = conductor_fixture(&[dir.path()]).await;
+    let dht = dir.path().join("databases").join("dht");
+
+    // fake sqlite header: 4096 byte pages, 3 pages, valid page count
+    let mut db = vec![0; 5 * 4096];
+    db[..16].copy_from_slice(b"SQLite format 3\0");
+    db[16..18].copy_from_slice(&4096_u16.to_be_bytes());
+    db[24..28].copy_from_slice(&7_u32.to_be_bytes());
+    db[28..32].copy_from_slice(&3_u32.to_be_bytes());
+    db[92..96].copy_from_slice(&7_u32.to_be_bytes());
+    tokio::fs::write(dht.join("space-a"), db).await.unwrap();
+    tokio::fs::write(dht.join("space-a-wal"), vec![0; 100])
+        .await
+        .unwrap();
+    tokio::fs::write(dht.join("space-a-other"), vec![0; 10])
+        .await
+        .unwrap();
+
+    c.db_size_mode = crate::db_size::DbSizeMode::Accurate;
+
+    let (proofs, _) = crate::db_size::check_db_size(&c).await.unwrap();
+    assert_eq!(2, proofs.len());
+
+    let a = proofs
+        .iter()
+        .map(|p| serde_json::from_str::<serde_json::Value>(p).unwrap())
+        .find(|p| p["d"] == "space-a")
+        .unwrap();
+    assert_eq!("12288", a["l"]);
+    assert_eq!("100", a["w"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_report_files_glob() {
+    use crate::reader::*;
+
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a");
+    let b = dir.path().join("a").join("b");
+    tokio::fs::create_dir_all(&b).await.unwrap();
+    for f in [
+        a.join("hc-report.jsonl"),
+        a.join("hc-report.jsonl.1"),
+        a.join("other.jsonl"),
+        b.join("hc-report.jsonl"),
+        b.join("hc-report-skip.jsonl"),
+    ] {
+        tokio::fs::write(f, b"").await.unwrap();
+    }
+
+    // plain directories are not recursive, and only take `*.jsonl`
+    let files =
+        list_report_files(std::slice::from_ref(&a), &ReadOptions::default())
+            .await
+            .unwrap();
+    assert_eq!(
+        vec![a.join("hc-report.jsonl"), a.join("other.jsonl")],
+        files
+    );
+
+    let pattern = dir.path().join("**").join("hc-report*.jsonl*");
+    let mut opts = ReadOptions {
+        exclude: vec!["**/*-skip.jsonl".into()],
+        max_depth: None,
+        ..Default::default()
+    };
+
+    let files = list_report_files(std::slice::from_ref(&pattern), &opts)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![
+            a.join("b").join("hc-report.jsonl"),
+            a.join("hc-report.jsonl"),
+            a.join("hc-report.jsonl.1"),
+        ],
+        files,
+    );
+
+    opts.max_depth = Some(2);
+    let files = list_report_files(std::slice::from_ref(&pattern), &opts)
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![a.join("hc-report.jsonl"), a.join("hc-report.jsonl.1")],
+        files,
+    );
+
+    // a plain path that doesn't exist is an error, not an empty glob
+    let err = list_report_files(&[dir.path().join("typo")], &opts)
+        .await
+        .unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+
+    // a symlink loop is skipped rather than failing the whole read
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&a, b.join("loop")).unwrap();
+        opts.max_depth = None;
+        let files = list_report_files(&[pattern], &opts).await.unwrap();
+        assert_eq!(
+            vec![
+                a.join("b").join("hc-report.jsonl"),
+                a.join("hc-report.jsonl"),
+                a.join("hc-report.jsonl.1"),
+            ],
+            files,
+        );
+    }
+}
+
+fn fetched_ops(t: u64) -> String {
+    format!("{{\"k\":\"fetchedOps\",\"t\":\"{t}\",\"d\":\"bobo\"}}\n")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_reports_compressed() {
+    use crate::reader::*;
+    use tokio::io::AsyncWriteExt;
+
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut enc =
+        async_compression::tokio::write::GzipEncoder::new(Vec::<u8>::new());
+    enc.write_all(fetched_ops(10).as_bytes()).await.unwrap();
+    enc.write_all(fetched_ops(11).as_bytes()).await.unwrap();
+    enc.shutdown().await.unwrap();
+    tokio::fs::write(dir.path().join("r.jsonl.gz"), enc.into_inner())
+        .await
+        .unwrap();
+
+    let mut enc =
+        async_compression::tokio::write::ZstdEncoder::new(Vec::<u8>::new());
+    enc.write_all(fetched_ops(12).as_bytes()).await.unwrap();
+    enc.shutdown().await.unwrap();
+    tokio::fs::write(dir.path().join("r.jsonl.zst"), enc.into_inner())
+        .await
+        .unwrap();
+
+    let path_list = vec![dir.path().to_path_buf()];
+    let opts = ReadOptions::default();
+
+    let mut sent = Vec::new();
+    let mut checkpoint = ReportCheckpoint::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(3, sent.len());
+    assert_eq!("12", checkpoint.last_record_timestamp);
+    assert_eq!(2, checkpoint.report_files.len());
+
+    // compressed files are not read again, even from an older checkpoint
+    checkpoint.last_record_timestamp = "0".into();
+    let mut sent = Vec::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    assert!(sent.is_empty());
+
+    // nor after a rotation renames them
+    tokio::fs::rename(
+        dir.path().join("r.jsonl.gz"),
+        dir.path().join("r.1.jsonl.gz"),
+    )
+    .await
+    .unwrap();
+    let mut sent = Vec::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    assert!(sent.is_empty());
+    assert!(
+        checkpoint
+            .report_files
+            .contains_key(&dir.path().join("r.1.jsonl.gz"))
+    );
+    assert!(
+        !checkpoint
+            .report_files
+            .contains_key(&dir.path().join("r.jsonl.gz"))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_reports_merged() {
+    use crate::reader::*;
+
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut a = String::new();
+    let mut b = String::new();
+    for t in 1..=150 {
+        if t % 3 == 0 {
+            a.push_str(&fetched_ops(t));
+        } else {
+            b.push_str(&fetched_ops(t));
+        }
+    }
+    tokio::fs::write(dir.path().join("a.jsonl"), a)
+        .await
+        .unwrap();
+    tokio::fs::write(dir.path().join("b.jsonl"), b)
+        .await
+        .unwrap();
+
+    let path_list = vec![dir.path().to_path_buf()];
+    let opts = ReadOptions::default();
+
+    let mut batches = Vec::new();
+    let mut checkpoint = ReportCheckpoint::new();
+    let res = read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        let t = proofs
+            .iter()
+            .map(|p| {
+                let p: serde_json::Value = serde_json::from_str(p).unwrap();
+                p["t"].as_str().unwrap().parse::<u64>().unwrap()
+            })
+            .collect::<Vec<_>>();
+        batches.push(t);
+        // reject the second batch
+        let res = match batches.len() {
+            1 => Ok(()),
+            _ => Err(std::io::Error::other("rejected")),
+        };
+        async { res }
+    })
+    .await;
+
+    assert!(res.is_err());
+    assert_eq!((1..=100).collect::<Vec<_>>(), batches[0]);
+    assert_eq!("100", checkpoint.last_record_timestamp);
+
+    // the rejected batch is retried
+    let mut sent = Vec::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(50, sent.len());
+    assert_eq!("150", checkpoint.last_record_timestamp);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_reports_pipelined() {
+    use crate::reader::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let dir = tempfile::tempdir().unwrap();
+
+    let mut content = String::new();
+    for t in 1..=500 {
+        content.push_str(&fetched_ops(t));
+    }
+    tokio::fs::write(dir.path().join("r.jsonl"), content)
+        .await
+        .unwrap();
+
+    let path_list = vec![dir.path().to_path_buf()];
+    let opts = ReadOptions {
+        upload_concurrency: 3,
+        ..Default::default()
+    };
+
+    let in_flight = AtomicUsize::new(0);
+    let max_in_flight = AtomicUsize::new(0);
+    let mut count = 0;
+
+    let mut checkpoint = ReportCheckpoint::new();
+    let res = read_reports(&path_list, &mut checkpoint, &opts, |_proofs| {
+        count += 1;
+        let n = count;
+        let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+        async move {
+            let cur = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(cur, Ordering::SeqCst);
+            // later batches finish first
+            tokio::time::sleep(std::time::Duration::from_millis((6 - n) * 20))
+                .await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            match n {
+                2 => Err(std::io::Error::other("rejected")),
+                _ => Ok(()),
+            }
+        }
+    })
+    .await;
+
+    assert!(res.is_err());
+    assert_eq!(3, max_in_flight.load(Ordering::SeqCst));
+    // the third batch was accepted, but the second was not
+    assert_eq!("100", checkpoint.last_record_timestamp);
+
+    let mut sent = Vec::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(400, sent.len());
+    assert_eq!("500", checkpoint.last_record_timestamp);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_reports_partial_lines() {
+    use crate::reader::*;
+    use tokio::io::AsyncWriteExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.jsonl");
+
+    let mut content = fetched_ops(1);
+    content.push_str(&format!(
+        "{{\"k\":\"fetchedOps\",\"t\":\"2\",\"d\":\"{}\"}}\n",
+        "x".repeat(200),
+    ));
+    content.push_str(&fetched_ops(3));
+    let partial = fetched_ops(4);
+    let (head, tail) = partial.split_at(10);
+    content.push_str(head);
+    tokio::fs::write(&path, content).await.unwrap();
+
+    let path_list = vec![dir.path().to_path_buf()];
+    let opts = ReadOptions {
+        max_line_bytes: 100,
+        ..Default::default()
+    };
+
+    let mut sent = Vec::new();
+    let mut checkpoint = ReportCheckpoint::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    // the over-long line is dropped, the partial line is left for later
+    assert_eq!(
+        vec![fetched_ops(1).trim(), fetched_ops(3).trim()],
+        sent.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+    );
+    assert_eq!("3", checkpoint.last_record_timestamp);
+
+    let mut f = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .await
+        .unwrap();
+    f.write_all(tail.as_bytes()).await.unwrap();
+    f.flush().await.unwrap();
+
+    let mut sent = Vec::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(vec![fetched_ops(4).trim().to_string()], sent);
+    assert_eq!(
+        tokio::fs::metadata(&path).await.unwrap().len(),
+        checkpoint.report_files[&path].offset,
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_reports_replaced() {
+    use crate::reader::*;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("r.jsonl");
+    tokio::fs::write(&path, (1..=2).map(fetched_ops).collect::<String>())
+        .await
+        .unwrap();
+
+    let path_list = vec![dir.path().to_path_buf()];
+    let opts = ReadOptions::default();
+
+    let mut checkpoint = ReportCheckpoint::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |_| async { Ok(()) })
+        .await
+        .unwrap();
+    assert_eq!("2", checkpoint.last_record_timestamp);
+
+    // a rotation replaces the file with a new one that grows past the old
+    // offset while the sender is down
+    let tmp = dir.path().join("r.jsonl.tmp");
+    tokio::fs::write(&tmp, (3..=8).map(fetched_ops).collect::<String>())
+        .await
+        .unwrap();
+    tokio::fs::rename(&tmp, &path).await.unwrap();
+
+    let mut sent = Vec::new();
+    read_reports(&path_list, &mut checkpoint, &opts, |proofs| {
+        sent.extend(proofs);
+        async { Ok(()) }
+    })
+    .await
+    .unwrap();
+
+    let expect: Vec<String> =
+        (3..=8).map(|t| fetched_ops(t).trim().to_string()).collect();
+    assert_eq!(expect, sent);
+    assert_eq!("8", checkpoint.last_record_timestamp);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_reports_quarantine() {
+    use crate::reader::*;
+
+    let dir = tempfile::tempdir().unwrap();
+    let reports = dir.path().join("reports");
+    tokio::fs::create_dir_all(&reports).await.unwrap();
+
+    let mut content = fetched_ops(1);
+    content.push_str("{\"k\":\"fetchedOps\",\"t\":\n");
+    content.push_str("{\"k\":\"fetchedOps\",\"t\":\"bad\"}\n");
+    // other kinds aren't ours to judge
+    content.push_str("{\"k\":\"other\",\"t\":\"bad\"}\n");
+    content.push_str(&fetched_ops(2));
+    tokio::fs::write(reports.join("r.jsonl"), content)
+        .await
+        .unwrap();
+
+    let dead_letter = dir.path().join("dead-letter.jsonl");
+    let opts = ReadOptions {
+        dead_letter_path: Some(dead_letter.clone()),
+        ..Default::default()
+    };
+
+    // nothing is quarantined until the batch is accepted, so a failed
+    // upload and its retry count each malformed line once
+    let mut checkpoint = ReportCheckpoint::new();
+    read_reports(
+        std::slice::from_ref(&reports),
+        &mut checkpoint,
+        &opts,
+        |_| async { Err(std::io::Error::other("down")) },
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(ParseErrorStats::default(), checkpoint.parse_errors);
+    assert!(!dead_letter.exists());
+
+    let mut sent = Vec::new();
+    read_reports(
+        std::slice::from_ref(&reports),
+        &mut checkpoint,
+        &opts,
+        |proofs| {
+            sent.extend(proofs);
+            async { Ok(()) }
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(2, sent.len());
+
+    let stats = &checkpoint.parse_errors;
+    assert_eq!(2, stats.total);
+    assert_eq!(Some(&1), stats.by_reason.get("invalidJson"));
+    assert_eq!(Some(&1), stats.by_reason.get("invalidTimestamp"));
+    assert!(stats.summary_proof().unwrap().is_some());
+
+    let dead = tokio::fs::read_to_string(&dead_letter).await.unwrap();
+    let lines = dead
+        .lines()
+        .map(|l| serde_json::from_str::<MalformedLine>(l).unwrap())
+        .map(|m| (m.line, m.reason))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![(2, "invalidJson".into()), (3, "invalidTimestamp".into())],
+        lines,
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dedup_across_restarts() {
+    use crate::dedup::*;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hashes");
+
+    let proofs = (0..5).map(fetched_ops).collect::<Vec<_>>();
+
+    let mut dedup = ProofDedup::load(path.clone(), 3).await.unwrap();
+    assert_eq!(5, dedup.filter(proofs.clone()).len());
+    dedup.record(&proofs[..4]).await.unwrap();
+
+    // only the most recent 3 are remembered
+    let dedup = ProofDedup::load(path.clone(), 3).await.unwrap();
+    assert_eq!(3, dedup.len());
+
+    let mut again = proofs.clone();
+    again.push(proofs[4].clone());
+    assert_eq!(
+        vec![proofs[0].clone(), proofs[4].clone()],
+        dedup.filter(again),
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn metrics_batch_size() {
+    use crate::client::*;
+    use crate::reader::*;
+
+    let dir = tempfile::tempdir().unwrap();
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        "http://127.0.0.1:8787".into(),
+        "bla".into(),
+        42,
+        60,
+        vec![dir.path().into()],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    // the computed size matches the real signed request body
+    let sizer = MetricsSizer::new(&c).unwrap();
+    let proofs = (1..=3).map(|t| fetched_ops(t).trim().to_string());
+    let proofs = proofs.collect::<Vec<_>>();
+    let expect = sizer.base()
+        + proofs.iter().map(|p| sizer.proof_size(p)).sum::<usize>()
+        - 1;
+    let signed = SignedMetrics::new(
+        &c,
+        proofs,
+        crate::unix_time().unwrap().as_millis() as u64,
+    )
+    .unwrap();
+    assert_eq!(expect, signed.body.len());
+
+    // batches are split on the byte budget
+    let reports = dir.path().join("reports");
+    tokio::fs::create_dir_all(&reports).await.unwrap();
+    let content = (1..=5).map(fetched_ops).collect::<String>();
+    tokio::fs::write(reports.join("r.jsonl"), content)
+        .await
+        .unwrap();
+
+    let one = sizer.proof_size(fetched_ops(1).trim());
+    let opts = ReadOptions {
+        batch_max_bytes: sizer.base() + one * 2,
+        sizer,
+        ..Default::default()
+    };
+
+    let mut batches = Vec::new();
+    let mut checkpoint = ReportCheckpoint::new();
+    read_reports(
+        std::slice::from_ref(&reports),
+        &mut checkpoint,
+        &opts,
+        |p| {
+            batches.push(p.len());
+            async { Ok(()) }
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(vec![2, 2, 1], batches);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rate_limiter() {
+    use crate::rate_limit::*;
+    use std::time::{Duration, Instant};
+
+    // bursts up to one second's worth, then paces
+    let limiter = RateLimiter::new(20.0, 0);
+    let start = Instant::now();
+    for _ in 0..20 {
+        limiter.acquire(0).await;
+    }
+    assert!(start.elapsed() < Duration::from_millis(200));
+    for _ in 0..5 {
+        limiter.acquire(0).await;
+    }
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // oversized requests wait for a full bucket, then go into deficit
+    limiter.set_limits(0.0, 1000);
+    let start = Instant::now();
+    limiter.acquire(800).await;
+    limiter.acquire(2000).await;
+    limiter.acquire(100).await;
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+
+    // pausing holds every request, even with limits disabled
+    limiter.set_limits(0.0, 0);
+    limiter.pause(Duration::from_millis(300));
+    let start = Instant::now();
+    limiter.acquire(0).await;
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+const TEST_CERT_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBiTCCAS+gAwIBAgIUdfzIBx/D05qMAZgVJFWa7NVHQ+wwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOY29sbGVjdG9yLnRlc3QwIBcNMjYxMDE5MDAyNTA1WhgPMjEy
+NjA5MjUwMDI1MDVaMBkxFzAVBgNVBAMMDmNvbGxlY3Rvci50ZXN0MFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAE70B6NXWhzhrhifPHbTLE/+vy0892hbCtrgiR+jkU
+racKCUW1bPYP5Z83h/Gm9vzrdtecOkVvNJeyDqnPBMbqBaNTMFEwHQYDVR0OBBYE
+FNXIHyzh6IM7J8S1TJM63+GXuL/aMB8GA1UdIwQYMBaAFNXIHyzh6IM7J8S1TJM6
+3+GXuL/aMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIgPWlxFapm
+kFsHvGS5pH7AK65o/OADlisOOGu7aeZLMboCIQDPvQaT6SIDUQHkTC0JgjW+w40f
+EKgu1s3IgyAviEOp0A==
+-----END CERTIFICATE-----
+";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn http_client_config() {
+    use crate::client::*;
+    use crate::crypto::*;
+    use base64::prelude::*;
+
+    // matches `openssl x509 -pubkey | openssl pkey -pubin -outform der
+    // | openssl dgst -sha256 -binary | base64`
+    let der = BASE64_STANDARD
+        .decode(
+            TEST_CERT_PEM
+                .lines()
+                .filter(|l| !l.starts_with("-----"))
+                .collect::<String>(),
+        )
+        .unwrap();
+    assert_eq!(
+        "70mRXBupf9tJuqZvKTa8Qxzswl3wla11L1+TZm2Jk3M=",
+        spki_sha256(&der).unwrap(),
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let ca = dir.path().join("ca.pem");
+    tokio::fs::write(&ca, TEST_CERT_PEM).await.unwrap();
+
+    let url = reqwest::Url::parse("https://127.0.0.1:8787").unwrap();
+
+    let http = HttpConfig {
+        proxy: Some("http://127.0.0.1:3128".into()),
+        ca_cert_path_list: vec![ca.clone()],
+        pinned_spki_sha256_list: vec![
+            "sha256//70mRXBupf9tJuqZvKTa8Qxzswl3wla11L1+TZm2Jk3M=".into(),
+        ],
+        ..Default::default()
+    };
+    Client::with_config(url.clone(), &http).await.unwrap();
+
+    // a client cert without its key is rejected
+    let http = HttpConfig {
+        client_cert_path: Some(ca),
+        ..Default::default()
+    };
+    assert!(Client::with_config(url, &http).await.is_err());
+
+    // older config files get the default timeouts
+    let c: HttpConfig = serde_json::from_str("{}").unwrap();
+    assert_eq!(HttpConfig::default(), c);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pinned_tls() {
+    use crate::client::*;
+    use crate::crypto::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    // a collector certificate for 127.0.0.1, issued by a test CA
+    let ca_key = rcgen::KeyPair::generate().unwrap();
+    let mut params = rcgen::CertificateParams::new(vec![]).unwrap();
+    params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = params.self_signed(&ca_key).unwrap();
+    let key = rcgen::KeyPair::generate().unwrap();
+    let cert = rcgen::CertificateParams::new(vec!["127.0.0.1".into()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+
+    let tls = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_no_client_auth()
+    .with_single_cert(
+        vec![cert.der().clone()],
+        rustls::pki_types::PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+    )
+    .unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(tls));
+
+    // the request bodies that made it to the collector
+    let bodies = Arc::new(Mutex::new(Vec::<Vec<u8>>::new()));
+    let bodies2 = bodies.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::task::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            let Ok(conn) = acceptor.accept(conn).await else {
+                continue;
+            };
+            let mut conn = tokio::io::BufReader::new(conn);
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                conn.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(v) =
+                    line.to_ascii_lowercase().strip_prefix("content-length:")
+                {
+                    len = v.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; len];
+            conn.read_exact(&mut body).await.unwrap();
+            bodies2.lock().unwrap().push(body);
+            let res = "HTTP/1.1 200 OK\r\nconnection: close\r\n\
+                content-length: 16\r\n\r\n{\"success\":true}";
+            conn.get_mut().write_all(res.as_bytes()).await.unwrap();
+            conn.get_mut().shutdown().await.unwrap();
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let ca_path = dir.path().join("ca.pem");
+    tokio::fs::write(&ca_path, ca.pem()).await.unwrap();
+    let url = reqwest::Url::parse(&format!("https://{addr}")).unwrap();
+
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let signed = SignedMetrics::new(&c, vec![fetched_ops(1)], 1000).unwrap();
+
+    let pin = spki_sha256(cert.der()).unwrap();
+    let http = HttpConfig {
+        ca_cert_path_list: vec![ca_path.clone()],
+        pinned_spki_sha256_list: vec![format!("sha256//{pin}")],
+        ..Default::default()
+    };
+    let client = Client::with_config(url.clone(), &http).await.unwrap();
+    client.submit_metrics(&signed).await.unwrap();
+    assert_eq!(
+        vec![signed.body.clone().into_bytes()],
+        *bodies.lock().unwrap()
+    );
+
+    // a wrong pin fails the handshake, before the body is sent
+    let http = HttpConfig {
+        ca_cert_path_list: vec![ca_path],
+        pinned_spki_sha256_list: vec![
+            "sha256//AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".into(),
+        ],
+        ..Default::default()
+    };
+    let client = Client::with_config(url, &http).await.unwrap();
+    let err = client.submit_metrics(&signed).await.unwrap_err();
+    let mut source: Option<&dyn std::error::Error> = Some(&err);
+    let mut chain = String::new();
+    while let Some(err) = source {
+        chain.push_str(&format!("{err}; "));
+        source = err.source();
+    }
+    assert!(chain.contains("does not match any pin"), "{chain}");
+    assert_eq!(1, bodies.lock().unwrap().len());
+
+    // pins can't be checked over plain http
+    let url = reqwest::Url::parse(&format!("http://{addr}")).unwrap();
+    assert!(Client::with_config(url, &http).await.is_err());
+}
+
+/// Serve http on a local port, answering each request with the response
+/// returned by `handler`, given the request head and body.
+async fn mock_collector<F>(handler: F) -> reqwest::Url
+where
+    F: Fn(&str, &[u8]) -> (u16, Vec<(String, String)>, String)
+        + Send
+        + Sync
+        + 'static,
+{
+    use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = std::sync::Arc::new(handler);
+
+    tokio::task::spawn(async move {
+        while let Ok((conn, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::task::spawn(async move {
+                let mut conn = tokio::io::BufReader::new(conn);
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    if conn.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let len = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                let mut body = vec![0; len];
+                conn.read_exact(&mut body).await.unwrap();
+
+                // hand the handler a decoded body, it can still check the
+                // content-encoding header
+                let encoding = head.lines().find_map(|l| {
+                    l.to_ascii_lowercase()
+                        .strip_prefix("content-encoding:")
+                        .map(|v| v.trim().to_string())
+                });
+                let body = match encoding.as_deref() {
+                    Some("gzip") => {
+                        let mut dec = GzipDecoder::new(&body[..]);
+                        let mut out = Vec::new();
+                        dec.read_to_end(&mut out).await.unwrap();
+                        out
+                    }
+                    Some("zstd") => {
+                        let mut dec = ZstdDecoder::new(&body[..]);
+                        let mut out = Vec::new();
+                        dec.read_to_end(&mut out).await.unwrap();
+                        out
+                    }
+                    _ => body,
+                };
+
+                let (status, headers, body) = handler(&head, &body);
+                let mut res = format!(
+                    "HTTP/1.1 {status} X\r\nconnection: close\r\n\
+                    content-length: {}\r\n",
+                    body.len()
+                );
+                for (k, v) in headers {
+                    res.push_str(&format!("{k}: {v}\r\n"));
+                }
+                res.push_str("\r\n");
+                res.push_str(&body);
+                let _ = conn.get_mut().write_all(res.as_bytes()).await;
+            });
+        }
+    });
+
+    reqwest::Url::parse(&format!("http://{addr}")).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clock_skew() {
+    use crate::client::*;
+
+    // the collector is two hours ahead, per its date header
+    let url = mock_collector(|_, _| {
+        let ahead = std::time::SystemTime::now()
+            + std::time::Duration::from_secs(2 * 60 * 60);
+        (
+            200,
+            vec![("date".into(), httpdate::fmt_http_date(ahead))],
+            r#"{"status":"healthy"}"#.into(),
+        )
+    })
+    .await;
+
+    let client = Client::new(url).await.unwrap();
+    assert_eq!(None, client.clock_skew_ms());
+
+    client.health().await.unwrap();
+    let skew = client.clock_skew_ms().unwrap();
+    assert!((skew - 7_200_000).abs() < 2000, "skew: {skew}");
+
+    // signatures use the local clock unless correction is enabled
+    let local = crate::unix_time().unwrap().as_millis() as i64;
+    let ts = client.signing_timestamp().unwrap() as i64;
+    assert!((ts - local).abs() < 2000);
+
+    client.set_clock_skew(30, true);
+    let ts = client.signing_timestamp().unwrap() as i64;
+    assert!((ts - local - 7_200_000).abs() < 2000);
+
+    // a precise time from the health response is preferred
+    let url = mock_collector(|_, _| {
+        let behind = crate::unix_time().unwrap().as_millis() as u64 - 1234;
+        (
+            200,
+            vec![],
+            format!(r#"{{"status":"healthy","time":{behind}}}"#),
+        )
+    })
+    .await;
+
+    let client = Client::new(url).await.unwrap();
+    client.health().await.unwrap();
+    let skew = client.clock_skew_ms().unwrap();
+    assert!((skew + 1234).abs() < 500, "skew: {skew}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn collectors_failover_and_fan_out() {
+    use crate::collectors::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn collector(ok: bool) -> (String, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let count2 = count.clone();
+        let url = mock_collector(move |head, _| {
+            if head.starts_with("GET / ") {
+                return (200, vec![], r#"{"status":"healthy"}"#.into());
+            }
+            count2.fetch_add(1, Ordering::SeqCst);
+            match ok {
+                true => (200, vec![], r#"{"success":true}"#.into()),
+                false => (500, vec![], "down".into()),
+            }
+        })
+        .await;
+        (url.to_string(), count)
+    }
+
+    let (down, down_count) = collector(false).await;
+    let (up, up_count) = collector(true).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        down,
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+    c.endpoints.extra_endpoints.push(ExtraEndpoint {
+        endpoint: up,
+        drone_id: 43,
+        collector_pub_key: None,
+    });
+
+    // failover skips the failing primary, and prefers the healthy
+    // endpoint from then on
+    let collectors = Collectors::new(&c).await.unwrap();
+    collectors.metrics(&c, vec!["a".into()]).await.unwrap();
+    collectors.metrics(&c, vec!["b".into()]).await.unwrap();
+    assert_eq!(1, down_count.load(Ordering::SeqCst));
+    assert_eq!(2, up_count.load(Ordering::SeqCst));
+
+    // fan-out requires every endpoint to accept by default
+    c.endpoints.endpoint_mode = EndpointMode::FanOut;
+    let collectors = Collectors::new(&c).await.unwrap();
+    assert!(collectors.metrics(&c, vec!["c".into()]).await.is_err());
+    assert_eq!(2, down_count.load(Ordering::SeqCst));
+    assert_eq!(3, up_count.load(Ordering::SeqCst));
+
+    c.endpoints.ack_policy = AckPolicy::Any;
+    let collectors = Collectors::new(&c).await.unwrap();
+    collectors.metrics(&c, vec!["c".into()]).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn collectors_sign_per_endpoint() {
+    use crate::collectors::*;
+    use std::sync::{Arc, Mutex};
+
+    // a collector whose clock is `ahead_ms` ahead, and the signing
+    // timestamps of the requests it received
+    async fn collector(ahead_ms: u64) -> (String, Arc<Mutex<Vec<u64>>>) {
+        let timestamps = Arc::new(Mutex::new(Vec::new()));
+        let timestamps2 = timestamps.clone();
+        let url = mock_collector(move |head, body| {
+            let now = crate::unix_time().unwrap().as_millis() as u64 + ahead_ms;
+            if head.starts_with("GET / ") {
+                return (
+                    200,
+                    vec![],
+                    format!(r#"{{"status":"healthy","time":{now}}}"#),
+                );
+            }
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            timestamps2
+                .lock()
+                .unwrap()
+                .push(body["timestamp"].as_u64().unwrap());
+            (200, vec![], r#"{"success":true}"#.into())
+        })
+        .await;
+        (url.to_string(), timestamps)
+    }
+
+    let (behind, behind_ts) = collector(0).await;
+    let (ahead, ahead_ts) = collector(7_200_000).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        behind,
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+    c.correct_clock_skew = true;
+    c.endpoints.extra_endpoints.push(ExtraEndpoint {
+        endpoint: ahead,
+        drone_id: 0,
+        collector_pub_key: None,
+    });
+    c.endpoints.endpoint_mode = EndpointMode::FanOut;
+
+    let collectors = Collectors::new(&c).await.unwrap();
+    collectors.health().await.unwrap();
+    collectors.metrics(&c, vec![fetched_ops(1)]).await.unwrap();
+
+    let local = crate::unix_time().unwrap().as_millis() as i64;
+    let behind_ts = behind_ts.lock().unwrap()[0] as i64;
+    let ahead_ts = ahead_ts.lock().unwrap()[0] as i64;
+    assert!((behind_ts - local).abs() < 2000, "{behind_ts}");
+    assert!((ahead_ts - local - 7_200_000).abs() < 2000, "{ahead_ts}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn collector_receipts() {
+    use crate::collectors::*;
+    use crate::crypto::*;
+    use crate::receipt::*;
+    use std::sync::{Arc, Mutex};
+
+    let (collector_pk, collector_sk) = generate_keypair().await.unwrap();
+    let collector_sk = Arc::new(collector_sk);
+
+    // what the mock collector sends back with each acknowledgement
+    let mode = Arc::new(Mutex::new("valid"));
+    let mode2 = mode.clone();
+    let url = mock_collector(move |_head, body| {
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let mut receipt = Receipt {
+            batch_id: body["batchId"].as_str().unwrap().into(),
+            accepted_at: 1_700_000_000_000,
+            signature: String::new(),
+        };
+        let content = receipt.signed_content().unwrap();
+        receipt.signature = collector_sk.sign(content.as_bytes()).unwrap();
+
+        let mode = *mode2.lock().unwrap();
+        let status = match mode {
+            "none" => {
+                return (200, vec![], r#"{"success":true}"#.into());
+            }
+            "wrongBatch" => {
+                receipt.batch_id = "other".into();
+                200
+            }
+            "forged" => {
+                receipt.accepted_at += 1;
+                200
+            }
+            "duplicate" => {
+                let res = serde_json::json!({
+                    "alreadyReceived": true,
+                    "batchId": body["batchId"],
+                    "receipt": receipt,
+                });
+                return (409, vec![], res.to_string());
+            }
+            _ => 200,
+        };
+        let res = serde_json::json!({ "success": true, "receipt": receipt });
+        (status, vec![], res.to_string())
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    // without a collector key, receipts are not required
+    let collectors = Collectors::new(&c).await.unwrap();
+    *mode.lock().unwrap() = "none";
+    collectors.metrics(&c, vec!["a".into()]).await.unwrap();
+    assert!(load_receipts(&c.receipt_path()).await.unwrap().is_empty());
+
+    c.collector_pub_key = Some(collector_pk.encode().unwrap());
+    let collectors = Collectors::new(&c).await.unwrap();
+
+    for (bad, expect) in [
+        ("none", "without a receipt"),
+        ("wrongBatch", "receipt is for batch other"),
+        ("forged", "invalid signature"),
+    ] {
+        *mode.lock().unwrap() = bad;
+        let err = collectors.metrics(&c, vec!["a".into()]).await.unwrap_err();
+        assert!(err.to_string().contains(expect), "{bad}: {err}");
+    }
+
+    for good in ["valid", "duplicate"] {
+        *mode.lock().unwrap() = good;
+        collectors.metrics(&c, vec![good.into()]).await.unwrap();
+    }
+
+    let receipts = load_receipts(&c.receipt_path()).await.unwrap();
+    assert_eq!(2, receipts.len());
+    assert_eq!(url.to_string(), receipts[0].endpoint);
+    assert_eq!(
+        batch_id(&c.drone_pub_key, &["valid".into()]),
+        receipts[0].receipt.batch_id,
+    );
+    receipts[1]
+        .receipt
+        .verify(
+            &PubKey::decode(c.collector_pub_key.as_ref().unwrap().as_bytes())
+                .unwrap(),
+            &batch_id(&c.drone_pub_key, &["duplicate".into()]),
+        )
+        .unwrap();
+
+    // each endpoint's receipts are checked against its own key
+    let (other_pk, other_sk) = generate_keypair().await.unwrap();
+    let other_url = mock_collector(move |_head, body| {
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let mut receipt = Receipt {
+            batch_id: body["batchId"].as_str().unwrap().into(),
+            accepted_at: 1_700_000_000_000,
+            signature: String::new(),
+        };
+        let content = receipt.signed_content().unwrap();
+        receipt.signature = other_sk.sign(content.as_bytes()).unwrap();
+        let res = serde_json::json!({ "success": true, "receipt": receipt });
+        (200, vec![], res.to_string())
+    })
+    .await;
+
+    *mode.lock().unwrap() = "valid";
+    let wrong_pk = c.collector_pub_key.clone();
+    c.endpoints.endpoint_mode = EndpointMode::FanOut;
+    c.endpoints.extra_endpoints.push(ExtraEndpoint {
+        endpoint: other_url.to_string(),
+        drone_id: 0,
+        collector_pub_key: wrong_pk,
+    });
+    let collectors = Collectors::new(&c).await.unwrap();
+    let err = collectors.metrics(&c, vec!["b".into()]).await.unwrap_err();
+    assert!(err.to_string().contains("invalid signature"), "{err}");
+
+    c.endpoints.extra_endpoints[0].collector_pub_key =
+        Some(other_pk.encode().unwrap());
+    let collectors = Collectors::new(&c).await.unwrap();
+    collectors.metrics(&c, vec!["b".into()]).await.unwrap();
+
+    let receipts = load_receipts(&c.receipt_path()).await.unwrap();
+    let endpoints: Vec<_> =
+        receipts[2..].iter().map(|r| r.endpoint.as_str()).collect();
+    assert_eq!(vec![url.as_str(), other_url.as_str()], endpoints);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn metrics_batch_id() {
+    use crate::client::*;
+    use std::sync::{Arc, Mutex};
+
+    // what the mock collector answers with, and the headers it saw
+    let conflict = Arc::new(Mutex::new(None::<(u16, serde_json::Value)>));
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let conflict2 = conflict.clone();
+    let heads2 = heads.clone();
+    let url = mock_collector(move |head, _body| {
+        heads2.lock().unwrap().push(head.to_ascii_lowercase());
+        match conflict2.lock().unwrap().clone() {
+            Some((status, res)) => (status, vec![], res.to_string()),
+            None => (200, vec![], r#"{"success":true}"#.into()),
+        }
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    // the id depends on the proofs only, not on when they were signed
+    let proofs = vec![fetched_ops(1), fetched_ops(2)];
+    let a = SignedMetrics::new(&c, proofs.clone(), 1000).unwrap();
+    let b = SignedMetrics::new(&c, proofs, 2000).unwrap();
+    let other = SignedMetrics::new(&c, vec![fetched_ops(3)], 1000).unwrap();
+    assert_eq!(a.batch_id, b.batch_id);
+    assert_ne!(a.body, b.body);
+    assert_ne!(a.batch_id, other.batch_id);
+
+    // it is part of the signed body, and sent as a header
+    let body: serde_json::Value = serde_json::from_str(&a.body).unwrap();
+    assert_eq!(a.batch_id, body["batchId"]);
+    assert_eq!(
+        a.batch_id,
+        SignedMetrics::from_body(a.body.clone()).unwrap().batch_id,
+    );
+
+    let client = Client::new(url).await.unwrap();
+    client.submit_metrics(&a).await.unwrap();
+    let header = format!("x-batch-id: {}", a.batch_id.to_ascii_lowercase());
+    assert!(heads.lock().unwrap()[0].contains(&header));
+
+    // an already received batch only counts as delivered if the collector
+    // confirms this very batch, with a conflict or a plain success status
+    for (res, ok) in [
+        (serde_json::json!({}), false),
+        (serde_json::json!({ "alreadyReceived": true }), false),
+        (
+            serde_json::json!({
+                "alreadyReceived": true,
+                "batchId": other.batch_id,
+            }),
+            false,
+        ),
+        (
+            serde_json::json!({
+                "alreadyReceived": true,
+                "batchId": a.batch_id,
+            }),
+            true,
+        ),
+    ] {
+        for status in [409, 200] {
+            *conflict.lock().unwrap() = Some((status, res.clone()));
+            let out = client.submit_metrics(&a).await;
+            assert_eq!(ok, out.is_ok(), "{status} {res}: {out:?}");
+        }
+    }
+    for (res, ok) in [
+        (serde_json::json!({ "duplicate": true }), false),
+        (
+            serde_json::json!({
+                "duplicate": true,
+                "batchId": other.batch_id,
+            }),
+            false,
+        ),
+        (
+            serde_json::json!({
+                "duplicate": true,
+                "batchId": a.batch_id,
+            }),
+            true,
+        ),
+    ] {
+        *conflict.lock().unwrap() = Some((200, res.clone()));
+        let out = client.submit_metrics(&a).await;
+        assert_eq!(ok, out.is_ok(), "{res}: {out:?}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn request_compression() {
+    use crate::client::*;
+    use std::sync::{Arc, Mutex};
+
+    // the content-encoding of each "/metrics" request, and whether the
+    // collector rejects compressed bodies
+    let encodings = Arc::new(Mutex::new(Vec::new()));
+    let reject = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let encodings2 = encodings.clone();
+    let reject2 = reject.clone();
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap();
+        if path == "/" {
+            return (
+                200,
+                vec![],
+                r#"{"status":"healthy","contentEncodings":["gzip","zstd"]}"#
+                    .into(),
+            );
+        }
+        let encoding = head
+            .lines()
+            .find_map(|l| {
+                l.to_ascii_lowercase()
+                    .strip_prefix("content-encoding:")
+                    .map(|v| v.trim().to_string())
+            })
+            .unwrap_or_else(|| "identity".into());
+        encodings2.lock().unwrap().push(encoding.clone());
+        if encoding != "identity"
+            && reject2.load(std::sync::atomic::Ordering::SeqCst)
+        {
+            return (415, vec![], String::new());
+        }
+        // the decoded body is the signed request
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert!(body["batchId"].is_string());
+        (200, vec![], r#"{"success":true}"#.into())
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let signed = SignedMetrics::new(&c, vec![fetched_ops(1)], 1000).unwrap();
+
+    for compression in [RequestCompression::Gzip, RequestCompression::Zstd] {
+        let mut client = Client::new(url.clone()).await.unwrap();
+        client.set_request_compression(compression);
+
+        // nothing is compressed until the collector advertises support
+        assert_eq!(RequestCompression::Identity, client.request_compression());
+        client.health().await.unwrap();
+        assert_eq!(compression, client.request_compression());
+
+        client.submit_metrics(&signed).await.unwrap();
+        assert_eq!(
+            vec![compression.as_str().to_string()],
+            std::mem::take(&mut *encodings.lock().unwrap()),
+        );
+    }
+
+    // a 415 is retried uncompressed, and compression stays off after
+    reject.store(true, std::sync::atomic::Ordering::SeqCst);
+    let mut client = Client::new(url).await.unwrap();
+    client.set_request_compression(RequestCompression::Gzip);
+    client.health().await.unwrap();
+    client.submit_metrics(&signed).await.unwrap();
+    assert_eq!(RequestCompression::Identity, client.request_compression());
+    client.submit_metrics(&signed).await.unwrap();
+    assert_eq!(
+        vec!["gzip", "identity", "identity"],
+        *encodings.lock().unwrap(),
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decommission() {
+    use std::sync::{Arc, Mutex};
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let requests2 = requests.clone();
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap().to_string();
+        if path == "/" {
+            return (200, vec![], r#"{"status":"healthy"}"#.into());
+        }
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        requests2.lock().unwrap().push((path, body));
+        (200, vec![], r#"{"success":true}"#.into())
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let reports = dir.path().join("reports");
+    tokio::fs::create_dir_all(&reports).await.unwrap();
+    let content = (1..=3).map(fetched_ops).collect::<String>();
+    tokio::fs::write(reports.join("r.jsonl"), content)
+        .await
+        .unwrap();
+
+    let file = dir.path().join("runtime-config.json");
+    let c = RuntimeConfigFile::with_init(
+        file.clone(),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![reports],
+        vec![],
+    )
+    .await
+    .unwrap();
+    let sec_key = c.drone_sec_key.clone();
+    drop(c);
+
+    let archive = dir.path().join("archive.json");
+    crate::decommission(file.clone(), Some(archive.clone()))
+        .await
+        .unwrap();
+
+    // unsent proofs are flushed before deregistering
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(2, requests.len());
+    assert_eq!("/metrics", requests[0].0);
+    assert_eq!(3, requests[0].1["metrics"].as_array().unwrap().len());
+    assert_eq!("/drone-deregistration", requests[1].0);
+    assert_eq!(42, requests[1].1["droneId"]);
+
+    assert!(!tokio::fs::try_exists(&file).await.unwrap());
+    let archived: RuntimeConfig = serde_json::from_str(
+        &tokio::fs::read_to_string(&archive).await.unwrap(),
+    )
+    .unwrap();
+    assert_eq!(sec_key, archived.drone_sec_key);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decommission_unknown_drone() {
+    use std::sync::{Arc, Mutex};
+
+    // how the collector answers the deregistration
+    let not_found = Arc::new(Mutex::new(String::new()));
+    let not_found2 = not_found.clone();
+    let url = mock_collector(move |head, _| {
+        let path = head.split(' ').nth(1).unwrap();
+        match path {
+            "/" => (200, vec![], r#"{"status":"healthy"}"#.into()),
+            "/drone-deregistration" => {
+                (404, vec![], not_found2.lock().unwrap().clone())
+            }
+            _ => (200, vec![], r#"{"success":true}"#.into()),
+        }
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("runtime-config.json");
+    RuntimeConfigFile::with_init(
+        file.clone(),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    // a plain 404 could be anything, so the key is kept
+    *not_found.lock().unwrap() = "Not Found".into();
+    assert!(crate::decommission(file.clone(), None).await.is_err());
+    assert!(tokio::fs::try_exists(&file).await.unwrap());
+
+    *not_found.lock().unwrap() = r#"{"code":"otherThing"}"#.into();
+    assert!(crate::decommission(file.clone(), None).await.is_err());
+    assert!(tokio::fs::try_exists(&file).await.unwrap());
+
+    // a drone the collector doesn't know is already deregistered
+    *not_found.lock().unwrap() =
+        format!(r#"{{"code":"{}"}}"#, crate::client::UNKNOWN_DRONE_CODE);
+    crate::decommission(file.clone(), None).await.unwrap();
+    assert!(!tokio::fs::try_exists(&file).await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_and_upload() {
+    use crate::bundle::*;
+    use crate::client::*;
+    use crate::crypto::*;
+    use crate::receipt::*;
+    use std::sync::{Arc, Mutex};
+
+    let dir = tempfile::tempdir().unwrap();
+    let reports = dir.path().join("reports");
+    tokio::fs::create_dir_all(&reports).await.unwrap();
+    let content = (1..=3).map(fetched_ops).collect::<String>();
+    tokio::fs::write(reports.join("r.jsonl"), &content)
+        .await
+        .unwrap();
+
+    // the exporting host has no collector
+    let file = dir.path().join("runtime-config.json");
+    let mut c = RuntimeConfigFile::with_init(
+        file.clone(),
+        "http://127.0.0.1:1".into(),
+        "bla".into(),
+        42,
+        60,
+        vec![reports.clone()],
+        vec![],
+    )
+    .await
+    .unwrap();
+    c.batch_max_proofs = 2;
+    c.write().await.unwrap();
+    drop(c);
+
+    let bundle = dir.path().join("bundle.jsonl");
+    let out = crate::export(file.clone(), bundle.clone()).await.unwrap();
+    assert_eq!(2, out.requests);
+    assert_eq!(3, out.proofs);
+
+    // the checkpoint advanced, so a second export adds only new proofs
+    tokio::fs::write(
+        reports.join("r.jsonl"),
+        format!("{content}{}", fetched_ops(4)),
+    )
+    .await
+    .unwrap();
+    let out = crate::export(file.clone(), bundle.clone()).await.unwrap();
+    assert_eq!(1, out.requests);
+    assert_eq!(1, out.proofs);
+
+    // requests keep the time they were signed at
+    let now = crate::unix_time().unwrap().as_millis() as u64;
+    for signed in read_bundle(&bundle).await.unwrap() {
+        assert!(signed.timestamp > now - 60_000 && signed.timestamp <= now);
+    }
+
+    let (collector_pk, collector_sk) = generate_keypair().await.unwrap();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received2 = received.clone();
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap();
+        if path == "/" {
+            return (200, vec![], r#"{"status":"healthy"}"#.into());
+        }
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let mut receipt = Receipt {
+            batch_id: body["batchId"].as_str().unwrap().into(),
+            accepted_at: 1_700_000_000_000,
+            signature: String::new(),
+        };
+        let content = receipt.signed_content().unwrap();
+        receipt.signature = collector_sk.sign(content.as_bytes()).unwrap();
+
+        let mut received = received2.lock().unwrap();
+        if received.contains(&body["batchId"]) {
+            let res = serde_json::json!({
+                "alreadyReceived": true,
+                "batchId": body["batchId"],
+                "receipt": receipt,
+            });
+            return (409, vec![], res.to_string());
+        }
+        received.push(body["batchId"].clone());
+        let res = serde_json::json!({ "success": true, "receipt": receipt });
+        (200, vec![], res.to_string())
+    })
+    .await;
+
+    // receipts signed by another key are rejected
+    let (wrong_pk, _) = generate_keypair().await.unwrap();
+    let out = crate::upload(
+        url.to_string(),
+        bundle.clone(),
+        HttpConfig::default(),
+        Some(wrong_pk.encode().unwrap()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(0, out.uploaded);
+    assert!(out.error.unwrap().contains("invalid signature"));
+    assert!(
+        load_receipts(&bundle_receipt_path(&bundle))
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    received.lock().unwrap().clear();
+    let out = crate::upload(
+        url.to_string(),
+        bundle.clone(),
+        HttpConfig::default(),
+        Some(collector_pk.encode().unwrap()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(3, out.requests);
+    assert_eq!(3, out.uploaded);
+    assert_eq!(3, out.receipts);
+    assert!(out.error.is_none());
+    assert_eq!(3, received.lock().unwrap().len());
+
+    let receipts = load_receipts(&bundle_receipt_path(&bundle)).await.unwrap();
+    assert_eq!(3, receipts.len());
+    for (stored, signed) in
+        receipts.iter().zip(read_bundle(&bundle).await.unwrap())
+    {
+        assert_eq!(url.to_string(), stored.endpoint);
+        stored
+            .receipt
+            .verify(&collector_pk, &signed.batch_id)
+            .unwrap();
+    }
+
+    // re-running is harmless
+    let out =
+        crate::upload(url.to_string(), bundle, HttpConfig::default(), None)
+            .await
+            .unwrap();
+    assert_eq!(3, out.uploaded);
+    assert_eq!(0, out.receipts);
+    assert_eq!(3, received.lock().unwrap().len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replay_range() {
+    use std::sync::{Arc, Mutex};
+
+    // timestamps of received proofs. The first batch containing "4" fails.
+    let received = Arc::new(Mutex::new(Vec::<String>::new()));
+    let replay_ids = Arc::new(Mutex::new(Vec::<String>::new()));
+    let failed = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let received2 = received.clone();
+    let replay_ids2 = replay_ids.clone();
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap();
+        if path == "/" {
+            return (200, vec![], r#"{"status":"healthy"}"#.into());
+        }
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let ts: Vec<String> = body["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| {
+                let p: serde_json::Value =
+                    serde_json::from_str(m["proof"].as_str().unwrap()).unwrap();
+                p["t"].as_str().unwrap().to_string()
+            })
+            .collect();
+
+        // the collector still remembers the original deliveries
+        let drone_pub_key = body["dronePubKey"].as_str().unwrap();
+        let proofs: Vec<String> = body["metrics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["proof"].as_str().unwrap().to_string())
+            .collect();
+        let batch_id = body["batchId"].as_str().unwrap();
+        if batch_id == crate::crypto::batch_id(drone_pub_key, &proofs) {
+            let res = serde_json::json!({
+                "alreadyReceived": true,
+                "batchId": batch_id,
+            });
+            return (409, vec![], res.to_string());
+        }
+        let replay_id = body["replayId"].as_str().unwrap();
+        assert_eq!(
+            batch_id,
+            crate::crypto::replay_batch_id(drone_pub_key, replay_id, &proofs),
+        );
+        replay_ids2.lock().unwrap().push(replay_id.to_string());
+
+        if ts.contains(&"4".to_string())
+            && !failed.swap(true, std::sync::atomic::Ordering::SeqCst)
+        {
+            return (500, vec![], "down".into());
+        }
+        received2.lock().unwrap().extend(ts);
+        (200, vec![], r#"{"success":true}"#.into())
+    })
+    .await;
+    let take = || std::mem::take(&mut *received.lock().unwrap());
+    let take_ids = || {
+        let mut ids = std::mem::take(&mut *replay_ids.lock().unwrap());
+        ids.dedup();
+        ids
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let reports = dir.path().join("reports");
+    tokio::fs::create_dir_all(&reports).await.unwrap();
+    let mut content = (1..=6).map(fetched_ops).collect::<String>();
+    content.push_str("{\"k\":\"other\",\"t\":\"4\"}\n");
+    tokio::fs::write(reports.join("r.jsonl"), content)
+        .await
+        .unwrap();
+
+    // the service has sent up to 5
+    let file = dir.path().join("runtime-config.json");
+    let mut c = RuntimeConfigFile::with_init(
+        file.clone(),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![reports],
+        vec![],
+    )
+    .await
+    .unwrap();
+    c.batch_max_proofs = 2;
+    c.report_checkpoint.last_record_timestamp = "5".into();
+    c.write().await.unwrap();
+    let state_path = c.replay_state_path();
+    drop(c);
+
+    // interrupted after the first batch
+    assert!(crate::replay(file.clone(), 2, 10, vec![]).await.is_err());
+    assert_eq!(vec!["2", "3"], take());
+    assert!(tokio::fs::try_exists(&state_path).await.unwrap());
+
+    // resumes after the last accepted batch, and stops at what the
+    // service has sent
+    let out = crate::replay(file.clone(), 2, 10, vec![]).await.unwrap();
+    assert!(out.resumed);
+    assert_eq!(5, out.to);
+    assert_eq!(4, out.proofs);
+    assert_eq!(vec!["4", "5"], take());
+    assert!(!tokio::fs::try_exists(&state_path).await.unwrap());
+
+    // the resumed replay kept its batch ids
+    let ids = take_ids();
+    assert_eq!(1, ids.len());
+
+    let out = crate::replay(file.clone(), 1, 5, vec!["other".into()])
+        .await
+        .unwrap();
+    assert!(!out.resumed);
+    assert_eq!(vec!["4"], take());
+    assert_ne!(ids, take_ids());
+
+    // a saved replay without an id is not resumed under a new one
+    tokio::fs::write(
+        &state_path,
+        r#"{"from":2,"to":10,"kinds":["fetchedOps"],"lastRecordTimestamp":3,"proofs":2}"#,
+    )
+    .await
+    .unwrap();
+    let err = crate::replay(file.clone(), 2, 10, vec![])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("replayId"), "{err}");
+    assert!(take().is_empty());
+
+    // the regular checkpoint is untouched
+    let c = RuntimeConfig::load_unlocked(&file).await.unwrap();
+    assert_eq!("5", c.report_checkpoint.last_record_timestamp);
+    assert!(c.report_checkpoint.report_files.is_empty());
+}
+
+/// A well-formed holochain hash with the given type prefix, distinct for
+/// each `n` below 16 and sorting in the same order.
+fn holo_hash(prefix: [u8; 3], n: u8) -> String {
+    use base64::prelude::*;
+
+    let core = [n << 4; 32];
+    let mut bytes = prefix.to_vec();
+    bytes.extend_from_slice(&core);
+    bytes.extend_from_slice(&crate::validate::dht_location(&core));
+    format!("u{}", BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+fn test_dna_hash(n: u8) -> String {
+    holo_hash([0x84, 0x2d, 0x24], n)
+}
+
+fn test_action_hash(n: u8) -> String {
+    holo_hash([0x84, 0x29, 0x24], n)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dna_registrations() {
+    use crate::client::*;
+    use std::sync::{Arc, Mutex};
+
+    let [a, b] = [1, 2].map(test_dna_hash);
+    let [p2, p3] = [2, 3].map(test_action_hash);
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let requests2 = requests.clone();
+    let (a2, b2, p22) = (a.clone(), b.clone(), p2.clone());
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap().to_string();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        requests2.lock().unwrap().push((path.clone(), body));
+        let res = match path.as_str() {
+            "/list-dnas" => serde_json::json!({
+                "success": true,
+                "registrations": [
+                    { "dnaHash": a2, "agreementId": "a1" },
+                    {
+                        "dnaHash": b2,
+                        "agreementId": "a2",
+                        "priceSheetHash": p22,
+                        "metadata": { "x": 1 },
+                    },
+                ],
+            }),
+            _ => serde_json::json!({ "success": true }),
+        };
+        (200, vec![], res.to_string())
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    let client = Client::new(url).await.unwrap();
+
+    let dnas = client.list_dnas(&c).await.unwrap();
+    assert_eq!(2, dnas.len());
+    assert_eq!(None, dnas[0].price_sheet_hash);
+    assert_eq!(Some(p2), dnas[1].price_sheet_hash);
+
+    client
+        .update_dna(&c, b.clone(), "a2".into(), Some(p3.clone()), None)
+        .await
+        .unwrap();
+    client
+        .unregister_dna(&c, a.clone(), "a1".into())
+        .await
+        .unwrap();
+    client
+        .register_dna(&c, a.clone(), "a1".into(), None, None)
+        .await
+        .unwrap();
+
+    // malformed input is rejected before anything is sent
+    let err = client
+        .update_dna(&c, "uhC0kB".into(), "a2".into(), None, None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("invalid dna hash"), "{err}");
+    // same prefix and length, different core
+    let bad_checksum = format!("{}{}", &b[..10], &a[10..]);
+    let err = client
+        .update_dna(&c, bad_checksum, "a2".into(), None, None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("checksum"), "{err}");
+    let err = client
+        .update_dna(&c, b.clone(), "a2".into(), Some(a.clone()), None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("price sheet hash"), "{err}");
+    let err = client
+        .unregister_dna(&c, a.clone(), " ".into())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("agreement id"), "{err}");
+
+    let requests = requests.lock().unwrap().clone();
+    let paths: Vec<_> = requests.iter().map(|(p, _)| p.as_str()).collect();
+    assert_eq!(
+        vec![
+            "/list-dnas",
+            "/update-dna",
+            "/unregister-dna",
+            "/register-dna"
+        ],
+        paths,
+    );
+    for (_, body) in requests.iter() {
+        assert_eq!(c.drone_pub_key, body["dronePubKey"]);
+        assert!(body["droneSignature"].is_string());
+    }
+    assert_eq!(p3, requests[1].1["priceSheetHash"]);
+    assert_eq!(a, requests[2].1["dnaHash"]);
+
+    // the signatures cover the action, so one can't stand in for another,
+    // except registrations, which keep their original signed content
+    let pub_key =
+        crate::crypto::PubKey::decode(c.drone_pub_key.as_bytes()).unwrap();
+    for (idx, sig) in [
+        (
+            0,
+            serde_json::json!({
+                "action": "list-dnas",
+                "dronePubKey": c.drone_pub_key,
+                "timestamp": requests[0].1["signatureTimestamp"],
+            }),
+        ),
+        (
+            1,
+            serde_json::json!({
+                "action": "update-dna",
+                "dronePubKey": c.drone_pub_key,
+                "dnaHash": b,
+                "agreementId": "a2",
+                "timestamp": requests[1].1["signatureTimestamp"],
+                "priceSheetHash": p3,
+            }),
+        ),
+        (
+            2,
+            serde_json::json!({
+                "action": "unregister-dna",
+                "dronePubKey": c.drone_pub_key,
+                "dnaHash": a,
+                "agreementId": "a1",
+                "timestamp": requests[2].1["signatureTimestamp"],
+            }),
+        ),
+        (
+            3,
+            serde_json::json!({
+                "dronePubKey": c.drone_pub_key,
+                "dnaHash": a,
+                "agreementId": "a1",
+                "timestamp": requests[3].1["signatureTimestamp"],
+            }),
+        ),
+    ] {
+        let signature = requests[idx].1["droneSignature"].as_str().unwrap();
+        pub_key
+            .verify(sig.to_string().as_bytes(), signature)
+            .unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sync_dnas() {
+    use crate::collectors::*;
+    use crate::dna_sync::*;
+
+    let [dna_a, dna_b, dna_c, dna_d, dna_z] =
+        [1, 2, 3, 4, 15].map(test_dna_hash);
+
+    let (dna_a2, dna_c2, dna_z2) =
+        (dna_a.clone(), dna_c.clone(), dna_z.clone());
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let res = match path {
+            "/list-dnas" => serde_json::json!({
+                "success": true,
+                "registrations": [
+                    { "dnaHash": dna_a2, "agreementId": "a0" },
+                    { "dnaHash": dna_z2, "agreementId": "a0" },
+                ],
+            }),
+            "/register-dna" if body["dnaHash"] == dna_c2 => {
+                return (500, vec![], "no such agreement".into());
+            }
+            _ => serde_json::json!({ "success": true }),
+        };
+        (200, vec![], res.to_string())
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let fixture = conductor_fixture(&[dir.path()]).await;
+    let dht = dir.path().join("databases").join("dht");
+    for name in [
+        format!("{dna_a}.sqlite3"),
+        format!("{dna_a}.sqlite3-wal"),
+        format!("{dna_b}.sqlite3"),
+        format!("{dna_c}.sqlite3"),
+        format!("{dna_d}.sqlite3"),
+        "not-a-dna.sqlite3".into(),
+    ] {
+        tokio::fs::write(dht.join(name), b"").await.unwrap();
+    }
+
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        fixture.conductor_config_path_list,
+    )
+    .await
+    .unwrap();
+
+    let hosted = hosted_dnas(&c).await.unwrap();
+    assert_eq!(
+        vec![&dna_a, &dna_b, &dna_c, &dna_d],
+        hosted.iter().collect::<Vec<_>>()
+    );
+
+    let agreements_path = dir.path().join("agreements.yaml");
+    tokio::fs::write(
+        &agreements_path,
+        format!("{dna_b}:\n  agreementId: a1\n{dna_c}:\n  agreementId: a2\n"),
+    )
+    .await
+    .unwrap();
+    let agreements = load_agreements(&agreements_path).await.unwrap();
+
+    let collectors = Collectors::new(&c).await.unwrap();
+
+    let reports = sync_registrations(&c, &collectors, &agreements, true)
+        .await
+        .unwrap();
+    assert_eq!(1, reports.len());
+    assert_eq!(url.to_string(), reports[0].endpoint);
+    assert_eq!(vec![dna_b.clone(), dna_c.clone()], reports[0].registered);
+    assert!(reports[0].failed.is_empty());
+
+    let reports = sync_registrations(&c, &collectors, &agreements, false)
+        .await
+        .unwrap();
+    let report = &reports[0];
+    assert_eq!(vec![dna_a.clone()], report.already_registered);
+    assert_eq!(vec![dna_b.clone()], report.registered);
+    assert_eq!(dna_c, report.failed[0].dna_hash);
+    assert_eq!(vec![dna_d], report.missing_agreement);
+    assert_eq!(vec![dna_z], report.not_hosted);
+    assert!(report.error.is_none());
+
+    // each endpoint is checked on its own
+    let down =
+        mock_collector(|_head, _body| (503, vec![], "down".into())).await;
+    let mut c = c;
+    c.endpoints.extra_endpoints.push(ExtraEndpoint {
+        endpoint: down.to_string(),
+        drone_id: 0,
+        collector_pub_key: None,
+    });
+    let collectors = Collectors::new(&c).await.unwrap();
+    let reports = sync_registrations(&c, &collectors, &agreements, true)
+        .await
+        .unwrap();
+    assert_eq!(2, reports.len());
+    assert_eq!(vec![dna_a], reports[0].already_registered);
+    assert_eq!(down.to_string(), reports[1].endpoint);
+    assert!(reports[1].error.as_ref().unwrap().contains("down"));
+    assert!(reports[1].registered.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn register_dna_manifest() {
+    use crate::collectors::*;
+    use crate::dna_sync::*;
+    use std::sync::{Arc, Mutex};
+
+    let [dna_a, dna_b, dna_c] = [1, 2, 3].map(test_dna_hash);
+    let [p1, p2] = [1, 2].map(test_action_hash);
+
+    // the collector remembers registrations, so re-runs see them
+    let registered = Arc::new(Mutex::new(vec![serde_json::json!({
+        "dnaHash": dna_a,
+        "agreementId": "a1",
+        "priceSheetHash": p1,
+    })]));
+    let registered2 = registered.clone();
+    let url = mock_collector(move |head, body| {
+        let path = head.split(' ').nth(1).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let mut registered = registered2.lock().unwrap();
+        match path {
+            "/list-dnas" => {
+                let res = serde_json::json!({
+                    "success": true,
+                    "registrations": *registered,
+                });
+                return (200, vec![], res.to_string());
+            }
+            _ if body["agreementId"] == "bad" => {
+                return (400, vec![], "unknown agreement".into());
+            }
+            "/register-dna" | "/update-dna" => {
+                registered.retain(|r| r["dnaHash"] != body["dnaHash"]);
+                registered.push(body);
+            }
+            _ => (),
+        }
+        (200, vec![], r#"{"success":true}"#.into())
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let c = RuntimeConfigFile::with_init(
+        dir.path().join("runtime-config.json"),
+        url.to_string(),
+        "bla".into(),
+        42,
+        60,
+        vec![],
+        vec![],
+    )
+    .await
+    .unwrap();
+
+    let manifest_path = dir.path().join("manifest.yaml");
+    tokio::fs::write(
+        &manifest_path,
+        format!(
+            "dnas:
+  - dnaHash: {dna_a}
+    agreementId: a1
+    priceSheetHash: {p2}
+  - dnaHash: {dna_b}
+    agreementId: a1
+    metadata:
+      name: app
+  - dnaHash: {dna_c}
+    agreementId: bad
+  - dnaHash: uhC0kD
+    agreementId: a1
+"
+        ),
+    )
+    .await
+    .unwrap();
+
+    let collectors = Collectors::new(&c).await.unwrap();
+
+    let manifest = load_manifest(&manifest_path).await.unwrap();
+    let res = register_manifest(&c, &collectors, manifest.clone()).await;
+    let status: Vec<_> = res.iter().map(|r| r.status).collect();
+    assert_eq!(
+        vec![
+            ManifestStatus::Updated,
+            ManifestStatus::Registered,
+            ManifestStatus::Failed,
+            ManifestStatus::Failed,
+        ],
+        status,
+    );
+    assert!(res[2].error.as_ref().unwrap().contains("unknown agreement"));
+    assert!(res[3].error.as_ref().unwrap().contains("invalid dna hash"));
+
+    // a re-run only retries the failure
+    let res = register_manifest(&c, &collectors, manifest.clone()).await;
+    let status: Vec<_> = res.iter().map(|r| r.status).collect();
+    assert_eq!(
+        vec![
+            ManifestStatus::Unchanged,
+            ManifestStatus::Unchanged,
+            ManifestStatus::Failed,
+            ManifestStatus::Failed,
+        ],
+        status,
+    );
+
+    // a second endpoint that was down is caught up by a re-run
+    let registered_b = Arc::new(Mutex::new(Vec::<serde_json::Value>::new()));
+    let up = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let (registered_b2, up2) = (registered_b.clone(), up.clone());
+    let url_b = mock_collector(move |head, body| {
+        if !up2.load(std::sync::atomic::Ordering::SeqCst) {
+            return (503, vec![], "down".into());
+        }
+        let path = head.split(' ').nth(1).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        let mut registered = registered_b2.lock().unwrap();
+        match path {
+            "/list-dnas" => {
+                let res = serde_json::json!({
+                    "success": true,
+                    "registrations": *registered,
+                });
+                return (200, vec![], res.to_string());
+            }
+            _ if body["agreementId"] == "bad" => {
+                return (400, vec![], "unknown agreement".into());
+            }
+            _ => registered.push(body),
+        }
+        (200, vec![], r#"{"success":true}"#.into())
+    })
+    .await;
+
+    let mut c = c;
+    c.endpoints.extra_endpoints.push(ExtraEndpoint {
+        endpoint: url_b.to_string(),
+        drone_id: 0,
+        collector_pub_key: None,
+    });
+    let collectors = Collectors::new(&c).await.unwrap();
+
+    let statuses = |res: &[ManifestResult], url: &reqwest::Url| {
+        res.iter()
+            .filter(|r| r.endpoint == url.as_str())
+            .map(|r| r.status)
+            .collect::<Vec<_>>()
+    };
+
+    let res = register_manifest(&c, &collectors, manifest.clone()).await;
+    assert_eq!(
+        vec![
+            ManifestStatus::Unchanged,
+            ManifestStatus::Unchanged,
+            ManifestStatus::Failed,
+            ManifestStatus::Failed,
+        ],
+        statuses(&res, &url),
+    );
+    assert_eq!(vec![ManifestStatus::Failed; 4], statuses(&res, &url_b));
+    assert!(res[4].error.as_ref().unwrap().contains("down"));
+
+    up.store(true, std::sync::atomic::Ordering::SeqCst);
+    let res = register_manifest(&c, &collectors, manifest).await;
+    assert_eq!(
+        vec![
+            ManifestStatus::Registered,
+            ManifestStatus::Registered,
+            ManifestStatus::Failed,
+            ManifestStatus::Failed,
+        ],
+        statuses(&res, &url_b),
+    );
+    assert_eq!(2, registered_b.lock().unwrap().len());
+
+    // a malformed entry is reported with its position
+    tokio::fs::write(&manifest_path, format!("- dnaHash: {dna_a}\n"))
+        .await
+        .unwrap();
+    let err = load_manifest(&manifest_path).await.unwrap_err();
+    assert!(err.to_string().contains("agreementId"), "{err}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn validate_dna_metadata() {
+    use crate::validate::*;
+
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": { "name": { "type": "string" } },
+        "required": ["name"],
+    });
+
+    validate_metadata(&serde_json::json!({ "name": "app" }), 64, Some(&schema))
+        .unwrap();
+
+    let bad = serde_json::json!({ "name": 1 });
+    validate_metadata(&bad, 64, None).unwrap();
+    let err = validate_metadata(&bad, 64, Some(&schema)).unwrap_err();
+    assert!(err.to_string().contains("/name"), "{err}");
+
+    let big = serde_json::json!({ "name": "x".repeat(64) });
+    let err = validate_metadata(&big, 64, None).unwrap_err();
+    assert!(err.to_string().contains("exceeds the 64 limit"), "{err}");
+
+    validate_agreement_id("a1").unwrap();
+    validate_agreement_id("").unwrap_err();
+    validate_agreement_id("a\n1").unwrap_err();
+    validate_agreement_id(&"a".repeat(AGREEMENT_ID_MAX_BYTES + 1)).unwrap_err();
 }
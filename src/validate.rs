@@ -0,0 +1,194 @@
+//! Validation of DNA registration fields before they are signed and sent.
+
+use crate::*;
+use base64::prelude::*;
+
+/// Default maximum size of serialized DNA registration metadata.
+pub const DEFAULT_DNA_METADATA_MAX_BYTES: usize = 16 * 1024;
+
+/// Maximum length of an agreement id.
+pub const AGREEMENT_ID_MAX_BYTES: usize = 256;
+
+/// Holochain hash type prefix bytes.
+const DNA_PREFIX: [u8; 3] = [0x84, 0x2d, 0x24];
+const ACTION_PREFIX: [u8; 3] = [0x84, 0x29, 0x24];
+const ENTRY_PREFIX: [u8; 3] = [0x84, 0x21, 0x24];
+
+/// Decode a Holochain hash string, checking its length and location
+/// checksum. Returns the 3-byte type prefix.
+fn decode_holo_hash(what: &str, hash: &str) -> Result<[u8; 3]> {
+    let err = |msg: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid {what} {hash:?}: {msg}"),
+        )
+    };
+
+    let Some(b64) = hash.strip_prefix('u') else {
+        return Err(err("expected a 'u' prefixed base64 hash".into()));
+    };
+
+    let bytes = BASE64_URL_SAFE_NO_PAD
+        .decode(b64)
+        .map_err(|e| err(format!("bad base64url: {e}")))?;
+
+    if bytes.len() != 39 {
+        return Err(err(format!("expected 39 bytes, got {}", bytes.len())));
+    }
+
+    let loc = dht_location(&bytes[3..35]);
+    if bytes[35..] != loc {
+        return Err(err("location checksum mismatch".into()));
+    }
+
+    Ok([bytes[0], bytes[1], bytes[2]])
+}
+
+/// The 4-byte dht location of a 32-byte hash core, as appended to
+/// Holochain hashes: a 16-byte blake2b hash folded by xor.
+pub(crate) fn dht_location(core: &[u8]) -> [u8; 4] {
+    use blake2::Digest;
+
+    let hash = blake2::Blake2b::<blake2::digest::consts::U16>::digest(core);
+    let mut out = [0; 4];
+    for chunk in hash.chunks(4) {
+        for (o, b) in out.iter_mut().zip(chunk) {
+            *o ^= b;
+        }
+    }
+    out
+}
+
+/// Check that `dna_hash` is a well-formed Holochain DNA hash.
+pub fn validate_dna_hash(dna_hash: &str) -> Result<()> {
+    if decode_holo_hash("dna hash", dna_hash)? != DNA_PREFIX {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "invalid dna hash {dna_hash:?}: expected a {} hash",
+                DNA_HASH_PREFIX,
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that `price_sheet_hash` is a well-formed Holochain action or
+/// entry hash, the forms in which a published price sheet is referenced.
+pub fn validate_price_sheet_hash(price_sheet_hash: &str) -> Result<()> {
+    let prefix = decode_holo_hash("price sheet hash", price_sheet_hash)?;
+    if prefix != ACTION_PREFIX && prefix != ENTRY_PREFIX {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "invalid price sheet hash {price_sheet_hash:?}: expected an \
+                 action (uhCkk) or entry (uhCEk) hash"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that `agreement_id` is non-empty, printable and not too long.
+pub fn validate_agreement_id(agreement_id: &str) -> Result<()> {
+    let msg = if agreement_id.trim().is_empty() {
+        "must not be empty"
+    } else if agreement_id.len() > AGREEMENT_ID_MAX_BYTES {
+        "too long"
+    } else if agreement_id.trim() != agreement_id {
+        "has leading or trailing whitespace"
+    } else if agreement_id.chars().any(char::is_control) {
+        "contains control characters"
+    } else {
+        return Ok(());
+    };
+
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("invalid agreement id {agreement_id:?}: {msg}"),
+    ))
+}
+
+/// Check that `metadata` is within `max_bytes` when serialized, and
+/// matches `schema` if given. Schema errors name the offending path.
+pub fn validate_metadata(
+    metadata: &serde_json::Value,
+    max_bytes: usize,
+    schema: Option<&serde_json::Value>,
+) -> Result<()> {
+    let err = |msg: String| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid metadata: {msg}"),
+        )
+    };
+
+    let len = serde_json::to_vec(metadata)?.len();
+    if len > max_bytes {
+        return Err(err(format!("{len} bytes exceeds the {max_bytes} limit")));
+    }
+
+    let Some(schema) = schema else {
+        return Ok(());
+    };
+
+    let compiled = jsonschema::JSONSchema::compile(schema).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid metadata schema: {e}"),
+        )
+    })?;
+
+    if let Err(errors) = compiled.validate(metadata) {
+        let errors: Vec<String> = errors
+            .map(|e| {
+                let path = e.instance_path.to_string();
+                let path = if path.is_empty() { "/".into() } else { path };
+                format!("{path}: {e}")
+            })
+            .collect();
+        return Err(err(errors.join("; ")));
+    }
+
+    Ok(())
+}
+
+/// Validate the fields of a DNA registration against the limits in
+/// `config`, so nothing malformed is signed.
+pub async fn validate_dna_registration(
+    config: &RuntimeConfig,
+    dna_hash: &str,
+    agreement_id: &str,
+    price_sheet_hash: Option<&str>,
+    metadata: Option<&serde_json::Value>,
+) -> Result<()> {
+    validate_dna_hash(dna_hash)?;
+    validate_agreement_id(agreement_id)?;
+
+    if let Some(price_sheet_hash) = price_sheet_hash {
+        validate_price_sheet_hash(price_sheet_hash)?;
+    }
+
+    if let Some(metadata) = metadata {
+        let schema = match &config.dna_metadata_schema_path {
+            Some(path) => Some(load_schema(path).await?),
+            None => None,
+        };
+        validate_metadata(
+            metadata,
+            config.dna_metadata_max_bytes,
+            schema.as_ref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+async fn load_schema(path: &std::path::Path) -> Result<serde_json::Value> {
+    let data = tokio::fs::read_to_string(path).await.map_err(|err| {
+        std::io::Error::new(err.kind(), format!("{}: {err}", path.display()))
+    })?;
+    serde_json::from_str(&data).map_err(|err| {
+        std::io::Error::other(format!("{}: {err}", path.display()))
+    })
+}